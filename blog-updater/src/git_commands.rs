@@ -1,22 +1,50 @@
 use super::CommandOutput;
 use super::new_err;
-use std::io::prelude::*;
+use std::collections::HashSet;
 use std::io;
 use std::path::PathBuf;
-use std::process::Command;
-use std::process::Stdio;
-use io::BufReader;
+use std::time::Duration;
+use exechelper::{Capture, Exec};
 
+/// every git command we run is local, so if one takes longer than this its
+/// probably stuck, eg: waiting for a lock, or for a credential helper
+pub const GIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// every path we give to git, or get from git, is relative to the root of
+/// the repository, so that it doesnt matter which directory we run git in
 pub fn get_git_command<T>(
     cmd: &[&str],
     filter: impl Fn(&CommandOutput) -> Result<T, String>,
 ) -> io::Result<T> {
-    let cmd_out = exechelper::execute(cmd)?;
+    let cmd_out: CommandOutput = Exec::from_args(cmd)?.timeout(GIT_TIMEOUT).output()?.into();
     let filtered = filter(&cmd_out)
         .map_err(|s| io::Error::new(io::ErrorKind::Other, s))?;
     Ok(filtered)
 }
 
+/// like get_git_command, but for commands that can output a lot, eg: every file
+/// in the repository. the output is read line by line, and only the lines that
+/// the filter returns Some for are kept. if git fails, the error is err_msg
+/// followed by what git printed to stderr
+pub fn get_git_lines<T>(
+    cmd: &[&str],
+    err_msg: impl Fn() -> String,
+    mut filter: impl FnMut(String) -> Option<T>,
+) -> io::Result<Vec<T>> {
+    let mut lines = Exec::from_args(cmd)?.timeout(GIT_TIMEOUT).lines()?;
+    let mut out_vec = vec![];
+    for line in &mut lines {
+        if let Some(item) = filter(line?) {
+            out_vec.push(item);
+        }
+    }
+    let output = lines.finish()?;
+    if !output.success() {
+        return Err(new_err(format!("{}: {}", err_msg(), output.stderr_string().trim_end())));
+    }
+    Ok(out_vec)
+}
+
 pub fn make_git_branch(branch_name: &str, from_ref: &str) -> io::Result<()> {
     let exec_args = [
         "git", "branch", branch_name, from_ref,
@@ -75,36 +103,20 @@ pub fn get_all_files_changed_since_last_blog_update(
     let exec_args = [
         "git", "diff", main_ref_branch_name, blog_branch_name, "--name-only",
     ];
-    let list = get_git_command(&exec_args, |cmdout| {
-        if cmdout.status != 0 {
-            let err_msg = format!("Failed to get files changed for revision between {} and {}", main_ref_branch_name, blog_branch_name);
-            Err(err_msg)
-        } else {
-            let list = cmdout.stdout.trim_end().split('\n')
-                .map(|n| n.to_string()).collect::<Vec<String>>();
-            Ok(list)
-        }
-    })?;
-    Ok(list)
+    get_git_lines(&exec_args, || {
+        format!("Failed to get files changed for revision between {} and {}", main_ref_branch_name, blog_branch_name)
+    }, Some)
 }
 
 pub fn get_all_timestamps_of_file_commits(
     blog_file_path: &str, main_ref_branch_name: &str,
 ) -> io::Result<Vec<String>> {
+    // pathspecs are relative to the current directory, unless they are marked with :(top)
+    let pathspec = format!(":(top){}", blog_file_path);
     let exec_args = [
-        "git", "log", main_ref_branch_name, "--date=unix", "--pretty=format:%cd,%an", "--", blog_file_path,
+        "git", "log", main_ref_branch_name, "--date=unix", "--pretty=format:%cd,%an", "--", &pathspec,
     ];
-    let list = get_git_command(&exec_args, |cmdout| {
-        if cmdout.status != 0 {
-            let err_msg = format!("Failed to get timestamps of changes to {}", blog_file_path);
-            Err(err_msg)
-        } else {
-            let list = cmdout.stdout.trim_end().split('\n')
-                .map(|n| n.to_string()).collect::<Vec<String>>();
-            Ok(list)
-        }
-    })?;
-    Ok(list)
+    get_git_lines(&exec_args, || format!("Failed to get timestamps of changes to {}", blog_file_path), Some)
 }
 
 pub fn get_git_toplevel_absolute_path() -> io::Result<PathBuf> {
@@ -134,6 +146,21 @@ pub fn get_blog_file_from_branch(blog_file_path: &str, branch_name: &str) -> io:
     })
 }
 
+/// like get_blog_file_from_branch, but for files that are not
+/// necessarily text, like images. the output isnt turned into a
+/// string, so we get the raw bytes of the blob
+pub fn get_file_bytes_from_branch(file_path: &str, branch_name: &str) -> io::Result<Vec<u8>> {
+    let refpath = format!("{}:{}", branch_name, file_path);
+    let output = Exec::new("git").args(&["show", &refpath])
+        .stderr(Capture::Null)
+        .timeout(GIT_TIMEOUT)
+        .output()?;
+    if !output.success() {
+        return Err(new_err(format!("Failed to get file {}", refpath)));
+    }
+    Ok(output.stdout)
+}
+
 pub fn can_blog_branch_be_fast_forwarded(blog_branch_name: &str, main_ref_branch_name: &str) -> io::Result<bool> {
     let exec_args = [
         "git", "merge-base", "--is-ancestor", blog_branch_name, main_ref_branch_name
@@ -151,25 +178,33 @@ pub fn delete_branch(branch_name: &str) -> io::Result<bool> {
 pub fn find_all_blog_files_from_git_tracked_files(
     blog_name: &str, main_ref_branch_name: &str,
 ) -> io::Result<Vec<String>> {
-    // for this one we will use a manual spawn and then loop through the output
-    // to avoid allocating a massive string, because some repositories can have
-    // tons of files.
-    let mut out_vec = vec![];
-    let mut cmd = Command::new("git");
-    cmd.arg("ls-tree").arg("-r").arg(main_ref_branch_name).arg("--name-only");
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::null());
-    cmd.stdin(Stdio::null());
-    let mut child = cmd.spawn()?;
-    let stdout = child.stdout.as_mut()
-        .map_or(Err(new_err("Failed to get git process standard output")), |s| Ok(s))?;
-    let reader = BufReader::new(stdout);
-    for line in reader.lines() {
-        let line = line?;
-        if line.ends_with(blog_name) {
-            out_vec.push(line);
-        }
-    }
+    // some repositories can have tons of files, so we
+    // only keep the lines that are blog files.
+    // without --full-tree, only the files in the current directory are listed
+    let exec_args = [
+        "git", "ls-tree", "--full-tree", "-r", main_ref_branch_name, "--name-only",
+    ];
+    get_git_lines(&exec_args, || format!("Failed to list the files of {}", main_ref_branch_name), |line| {
+        if line.ends_with(blog_name) { Some(line) } else { None }
+    })
+}
 
-    Ok(out_vec)
+/// every file on the ref, except the ones that is_excluded returns true for.
+/// directories and submodules are not files, so they are never in the set.
+/// quotePath is off so that paths with non ascii characters arent quoted
+pub fn get_all_files_of_ref(
+    main_ref_branch_name: &str, is_excluded: impl Fn(&str) -> bool,
+) -> io::Result<HashSet<String>> {
+    let exec_args = [
+        "git", "-c", "core.quotePath=false", "ls-tree", "--full-tree", "-r", main_ref_branch_name,
+    ];
+    let files = get_git_lines(&exec_args, || format!("Failed to list the files of {}", main_ref_branch_name), |line| {
+        // each line is: <mode> <type> <object>\t<path>
+        let (info, path) = line.split_once('\t')?;
+        if info.split(' ').nth(1) != Some("blob") || is_excluded(path) {
+            return None;
+        }
+        Some(path.to_string())
+    })?;
+    Ok(files.into_iter().collect())
 }
@@ -0,0 +1,81 @@
+use pulldown_cmark::escape::escape_html;
+use std::sync::OnceLock;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator, css_for_theme_with_class_style};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+pub const HIGHLIGHT_CSS_ENDING: &str = "highlight.css";
+pub const DEFAULT_HIGHLIGHT_THEME: &str = "base16-ocean.dark";
+
+/// the classes are prefixed so that they dont clash
+/// with the classes of the markdown stylesheet
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// loading the syntax definitions is slow, so we only want to do it once
+/// no matter how many blog posts we render
+fn get_syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// the info string of a fenced code block can have more than
+/// just the language, eg: ```rust,ignore
+pub fn get_language_from_info_string(info_string: &str) -> &str {
+    info_string.split(|c: char| c == ',' || c.is_whitespace())
+        .next().unwrap_or("")
+}
+
+/// returns the html of a code block where every token is wrapped in a span
+/// with a class that the highlight.css stylesheet will color. if we dont
+/// know the language, the code block is rendered the same as it would be without
+/// syntax highlighting
+pub fn highlight_code_block(info_string: &str, code: &str) -> String {
+    let language = get_language_from_info_string(info_string);
+    let mut html_out = String::from("");
+    let syntax_set = get_syntax_set();
+    let syntax = if language.is_empty() { None } else { syntax_set.find_syntax_by_token(language) };
+
+    let highlighted = syntax.and_then(|syntax| {
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line).ok()?;
+        }
+        Some(generator.finalize())
+    });
+
+    match highlighted {
+        Some(highlighted) => {
+            html_out.push_str("<pre class=\"hl-code\"><code class=\"language-");
+            let _ = escape_html(&mut html_out, language);
+            html_out.push_str("\">");
+            html_out.push_str(&highlighted);
+        }
+        None => {
+            if language.is_empty() {
+                html_out.push_str("<pre><code>");
+            } else {
+                html_out.push_str("<pre><code class=\"language-");
+                let _ = escape_html(&mut html_out, language);
+                html_out.push_str("\">");
+            }
+            let _ = escape_html(&mut html_out, code);
+        }
+    }
+    html_out.push_str("</code></pre>\n");
+    html_out
+}
+
+/// the stylesheet that colors the output of highlight_code_block
+pub fn get_highlight_css(theme_name: &str) -> Result<String, String> {
+    let theme_set = ThemeSet::load_defaults();
+    let theme = match theme_set.themes.get(theme_name) {
+        Some(t) => t,
+        None => {
+            let available: Vec<&String> = theme_set.themes.keys().collect();
+            return Err(format!("Unknown syntax highlighting theme '{}'. Available themes are: {:?}", theme_name, available));
+        }
+    };
+    css_for_theme_with_class_style(theme, CLASS_STYLE)
+        .map_err(|e| format!("Failed to create syntax highlighting stylesheet: {}", e))
+}
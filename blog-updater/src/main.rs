@@ -1,11 +1,12 @@
 use gumdrop::Options;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
-use std::{collections::HashMap, io};
+use std::{collections::{HashMap, HashSet}, io};
 use exechelper::CommandOutput;
 use serde::{Serialize, Deserialize};
-use pulldown_cmark::{Parser, html};
-use context_based_variable_substitution::{ replace_all_from_ex, FailureModeEx };
+use context_based_variable_substitution::{ render_template_with_diagnostics, FailureMode, RenderOptions, RawHtmlKeys, Diagnostic, SubstitutionError, Template, Value, Scope, Node, Segment, Syntax };
+use context_based_variable_substitution::{ Context, ContextChain, EnvContext, CommandContext };
+use context_based_variable_substitution::{ escape_html_attribute, escape_html_text };
 use simple_interaction as interact;
 use chrono;
 
@@ -15,7 +16,35 @@ use git_commands::*;
 mod rss;
 use rss::*;
 
+mod sitemap;
+use sitemap::*;
+
+mod urls;
+use urls::*;
+
+mod markdown;
+use markdown::*;
+
+mod highlight;
+use highlight::*;
+
 pub const DEFAULT_BLOG_CONFIG_NAME: &str = "blogconfig.json";
+/// used if nothing in the title can be made into a url
+pub const DEFAULT_BLOG_FILE_NAME: &str = "post";
+/// the template keys whose values we generate as html. every other
+/// value is escaped when it is inserted into a template
+pub const HTML_TEMPLATE_KEYS: &[&str] = &[
+    "rendered_markdown", "rendered_body", "byline_html", "about_me_html", "toc_html",
+    "meta_tags", "publisher_tag", "blog_post_links_html", "links_html",
+];
+
+/// the keys that a template for the new layout uses to place the parts
+/// of a blog post that the legacy layout puts in rendered_markdown
+pub const LAYOUT_TEMPLATE_KEYS: &[&str] = &["rendered_body", "byline_html", "about_me_html"];
+
+/// `${{ key }}` is a key of the blog config, `E{{ HOME }}` is an environment
+/// variable, and `!{{ command }}` is the output of a command if template_commands is on
+pub const TEMPLATE_SYNTAX_CHARS: &str = "$E!";
 
 #[derive(Debug, Options)]
 pub struct Cli {
@@ -46,6 +75,15 @@ pub struct Cli {
     /// by default, this program will create an RSS xml file. pass --no-rss to disable the creation of an RSS file.
     pub no_rss: bool,
 
+    /// by default, this program will create a sitemap.xml file. pass --no-sitemap to disable the creation of a sitemap.
+    pub no_sitemap: bool,
+
+    /// also create a robots.txt file that points crawlers to the sitemap. this is ignored if --no-sitemap is passed.
+    pub robots_txt: bool,
+
+    /// by default, template keys that have no value are replaced with nothing, and a warning is printed. pass --strict to fail instead of writing a file that has missing values.
+    pub strict: bool,
+
     /// dont update the blogs branch to current head. the default is to ask this in interactive mode. if running with --no-interactive, then default is to NOT update the blogs branch, regardless of this --no-update flag
     pub no_update: bool,
 
@@ -69,8 +107,15 @@ pub struct BlogConfig {
     
     // generated:
     pub blog_file_name: Option<String>,
+    pub blog_post_path: Option<String>,
     pub published_time_iso: Option<String>,
     pub modified_time_iso: Option<String>,
+    pub word_count: Option<usize>,
+    pub reading_time_minutes: Option<usize>,
+    /// the root of the git repository, where template commands are run.
+    /// its not a template key, and it cant be set in a config file
+    #[serde(skip)]
+    pub git_root: Option<PathBuf>,
 
 
     // these probably only should come from the blog config:
@@ -81,6 +126,15 @@ pub struct BlogConfig {
     pub blog_name: Option<String>,
     pub blog_home_url: Option<String>,
     pub blog_description: Option<String>,
+    pub url_style: Option<UrlStyle>,
+    pub permalink: Option<String>,
+    pub on_slug_collision: Option<SlugCollision>,
+    pub markdown: Option<MarkdownConfig>,
+    pub syntax_theme: Option<String>,
+    pub toc: Option<bool>,
+    pub legacy_layout: Option<bool>,
+    /// let templates run commands with `!{{ command }}`, eg: `!{{ git rev-parse --short HEAD }}`
+    pub template_commands: Option<bool>,
 }
 
 impl BlogConfig {
@@ -124,65 +178,83 @@ impl BlogConfig {
         if let Some(s) = other.blog_description {
             self.blog_description = Some(s);
         }
+        if let Some(s) = other.url_style {
+            self.url_style = Some(s);
+        }
+        if let Some(s) = other.permalink {
+            self.permalink = Some(s);
+        }
+        if let Some(s) = other.on_slug_collision {
+            self.on_slug_collision = Some(s);
+        }
+        if let Some(s) = other.syntax_theme {
+            self.syntax_theme = Some(s);
+        }
+        if let Some(b) = other.toc {
+            self.toc = Some(b);
+        }
+        if let Some(b) = other.legacy_layout {
+            self.legacy_layout = Some(b);
+        }
+        if let Some(b) = other.template_commands {
+            self.template_commands = Some(b);
+        }
+        if let Some(m) = other.markdown {
+            // the blog header might only override some of the markdown
+            // options, so we merge them instead of replacing all of them
+            match &mut self.markdown {
+                Some(existing) => existing.apply(m),
+                None => self.markdown = Some(m),
+            }
+        }
+    }
+
+    /// the path after the blog_home_url that links to this blog post.
+    /// this is the permalink pattern expanded for this blog file, with the url_style
+    /// applied, eg: it can be `my-post`, `2021/05/my-post.html`, or `my-post/`
+    pub fn get_blog_post_path(&self, blog_file: &BlogFile) -> io::Result<Option<String>> {
+        let blog_file_name = match &self.blog_file_name {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let pattern = self.permalink.as_deref().unwrap_or(DEFAULT_PERMALINK);
+        let expanded = expand_permalink(
+            pattern, blog_file_name, &blog_file.path_from_root, blog_file.written)
+            .map_err(new_err)?;
+        let url_style = self.url_style.unwrap_or_default();
+        Ok(Some(url_style.get_url_path(&expanded)))
     }
 
-    pub fn to_hashmap_context<'a>(&'a self, markdown: &'a Option<String>) -> HashMap<&'a str, String> {
-        let mut context = HashMap::new();
+    /// where this blog post should be written to, relative to the output directory
+    pub fn get_blog_post_output_path(&self) -> Option<PathBuf> {
+        self.blog_post_path.as_ref().map(|p| get_output_path_from_url_path(p))
+    }
+
+    /// lists like the tags are inserted as one key per item: `tags.0`, `tags.1`, etc.
+    /// so that templates can loop over them with `${% for tag in tags %}`
+    /// every field of the config can be used in a template, eg: `${{ title }}`,
+    /// `${{ tags.0 }}` or `${{ markdown.tables }}`, along with some keys that are generated from them
+    pub fn to_context(&self, markdown: &Option<String>) -> Value {
+        // the config is only made of strings, numbers, bools and lists, so this cant fail
+        let mut context = Value::from_serialize(self).unwrap_or_default();
         if let Some(ref m) = markdown {
             context.insert("rendered_markdown", m.clone());
         }
-        if let Some(s) = &self.title {
-            context.insert("title", s.clone());
-        }
-        if let Some(s) = &self.description {
-            context.insert("description", s.clone());
-        }
         if let Some(t) = &self.tags {
             let mut meta_tag_str = "".into();
             for tag in t {
-                let this_tag = format!("<meta property=\"article:tag\" content=\"{}\">", tag);
+                let this_tag = format!("<meta property=\"article:tag\" content=\"{}\">", escape_html_attribute(tag));
                 meta_tag_str = format!("{}{}\n", meta_tag_str, this_tag);
             }
             context.insert("meta_tags", meta_tag_str);
         }
-        if let Some(s) = &self.date_written {
-            context.insert("date_written", s.clone());
-        }
-        if let Some(s) = &self.date_updated {
-            context.insert("date_updated", s.clone());
+        if let Some(p) = &self.blog_post_path {
+            context.insert("root_path", get_root_path_from_url_path(p));
         }
         if let Some(s) = &self.author_name {
-            context.insert("author_name", s.clone());
-            let publisher_tag = format!("<meta property=\"article:publisher\" content=\"{}\">", s.clone());
+            let publisher_tag = format!("<meta property=\"article:publisher\" content=\"{}\">", escape_html_attribute(s));
             context.insert("publisher_tag", publisher_tag);
         }
-        if let Some(s) = &self.author_url {
-            context.insert("author_url", s.clone());
-        }
-        if let Some(s) = &self.author_email {
-            context.insert("author_email", s.clone());
-        }
-        if let Some(s) = &self.author_projects_url {
-            context.insert("author_projects_url", s.clone());
-        }
-        if let Some(s) = &self.blog_name {
-            context.insert("blog_name", s.clone());
-        }
-        if let Some(s) = &self.blog_home_url {
-            context.insert("blog_home_url", s.clone());
-        }
-        if let Some(s) = &self.blog_file_name {
-            context.insert("blog_file_name", s.clone());
-        }
-        if let Some(s) = &self.blog_description {
-            context.insert("blog_description", s.clone());
-        }
-        if let Some(s) = &self.modified_time_iso {
-            context.insert("modified_time_iso", s.clone());
-        }
-        if let Some(s) = &self.published_time_iso {
-            context.insert("published_time_iso", s.clone());
-        }
         context
     }
 }
@@ -193,6 +265,9 @@ pub struct BlogFile {
     pub written: i64,
     pub updated: i64,
     pub git_author_name: String,
+    /// the blog_file_name after resolving collisions with every other
+    /// blog file. if set, this takes precedence over the blog header and the title
+    pub blog_file_name: Option<String>,
 }
 
 /// steps for updating blogs:
@@ -236,6 +311,7 @@ pub fn get_blog_file_meta_info(
                 written: 0,
                 updated: 0,
                 git_author_name: "".into(),
+                blog_file_name: None,
             };
             // the first commit in the list is the most recent update
             if let Some(first_update) = updates.first() {
@@ -256,12 +332,17 @@ pub fn get_blog_file_meta_info(
     Ok(out_vec)
 }
 
-pub fn get_all_blog_files_changed_since_last_blog_update(
+/// the blog files are from get_all_blog_files_ever, so we only need to filter
+/// them down to the ones that changed. this also skips blog files that were deleted
+pub fn get_all_blog_files_changed_since_last_blog_update<'a>(
     blog_branch_name: &str, main_ref_branch_name: &str,
-    blog_file_name: &str,
-) -> io::Result<Vec<BlogFile>> {
+    all_blog_files: &'a [BlogFile],
+) -> io::Result<Vec<&'a BlogFile>> {
     let files_changed = get_all_files_changed_since_last_blog_update(blog_branch_name, main_ref_branch_name)?;
-    get_blog_file_meta_info(&files_changed, blog_file_name, main_ref_branch_name)
+    let updated_blogs = all_blog_files.iter()
+        .filter(|b| files_changed.contains(&b.path_from_root))
+        .collect();
+    Ok(updated_blogs)
 }
 
 pub fn handle_branch_missing(
@@ -391,7 +472,7 @@ pub fn get_all_blog_files_ever(
 }
 
 pub fn get_blog_post_link_template(template: &Option<PathBuf>) -> io::Result<String> {
-    let default_template = "<div class=\"bloglink\"><a class=\"abloglink\" href=\"${{ blog_home_url | / }}/${{ blog_file_name }}\">${{ date_written }} - ${{ title }}</a></div>";
+    let default_template = "<div class=\"bloglink\"><a class=\"abloglink\" href=\"${{ blog_home_url | / }}/${{ blog_post_path }}\">${{ date_written }} - ${{ title }}</a></div>";
     match template {
         Some(path) => std::fs::read_to_string(path),
         None => Ok(default_template.to_string())
@@ -406,6 +487,118 @@ pub fn get_blog_homepage_template(template: &Option<PathBuf>) -> io::Result<Stri
     }
 }
 
+/// templates can `${% include %}` files that are next to them.
+/// the default templates dont include anything
+pub fn get_template_include_dir(template: &Option<PathBuf>) -> Option<PathBuf> {
+    template.as_ref().and_then(|path| path.parent()).map(|p| p.to_path_buf())
+}
+
+/// the name that warnings use for a template: its path,
+/// or the given name if we are using the default template
+pub fn get_template_name(template: &Option<PathBuf>, default_name: &str) -> String {
+    match template {
+        Some(path) => path.display().to_string(),
+        None => default_name.to_string(),
+    }
+}
+
+/// if any placeholder, if or for block of the template, or of the files it includes, uses one of the keys
+pub fn template_uses_any_key(nodes: &[Node], keys: &[&str]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Text { segments } => segments.iter().any(|segment| match segment {
+            Segment::Placeholder(p) => p.syntax_char == '$' && keys.contains(&p.key.key.as_str()),
+            Segment::Text(_) => false,
+        }),
+        Node::If { key, syntax_char, then_nodes, else_nodes, .. } => {
+            (*syntax_char == '$' && keys.contains(&key.as_str()))
+                || template_uses_any_key(then_nodes, keys)
+                || template_uses_any_key(else_nodes, keys)
+        }
+        Node::For { body, .. } => template_uses_any_key(body, keys),
+    })
+}
+
+/// custom templates that were written before the new layout only use rendered_markdown,
+/// so they keep the legacy layout unless the blog config says otherwise.
+/// the default template always uses the new layout
+pub fn set_default_layout(blog_config: &mut BlogConfig, template: &Template, template_path: &Option<PathBuf>) {
+    if blog_config.legacy_layout.is_some() || template_path.is_none() {
+        return;
+    }
+    if !template_uses_any_key(&template.nodes, LAYOUT_TEMPLATE_KEYS) {
+        eprintln!("WARN: {} does not use any of {}, so the legacy layout is used. Set legacy_layout in the blog config to choose the layout",
+            get_template_name(template_path, "default.html"), LAYOUT_TEMPLATE_KEYS.join(", "));
+        blog_config.legacy_layout = Some(true);
+    }
+}
+
+/// prints the placeholders of a template that had no value, eg:
+/// `WARN: template.html:12:5: unknown key 'description' (while rendering blog.md)`
+/// or if strict, returns an error with all of them instead
+pub fn check_template_diagnostics(
+    diagnostics: &[Diagnostic],
+    template_name: &str,
+    rendering: &str,
+    strict: bool,
+) -> io::Result<()> {
+    let unresolved: Vec<Diagnostic> = diagnostics.iter().filter(|d| d.is_unresolved()).cloned().collect();
+    if strict && !unresolved.is_empty() {
+        let err = SubstitutionError::MissingKeys(unresolved);
+        return Err(new_err(format!("{} (while rendering {})", err.to_string_with_template_name(template_name), rendering)));
+    }
+    for diagnostic in unresolved {
+        eprintln!("WARN: {} (while rendering {})", diagnostic.to_string_with_template_name(template_name), rendering);
+    }
+    Ok(())
+}
+
+/// blog templates can output a placeholder as is with `\${{ key }}`
+pub fn get_template_syntax() -> Syntax {
+    // the default delimiters are valid, so this cant fail
+    Syntax::builder().escape_char(Some('\\')).build().unwrap()
+}
+
+/// templates that are rendered for every blog post are compiled
+/// once up front, so that we dont parse the same template over and over
+pub fn compile_template(template: &str, include_dir: Option<&Path>) -> io::Result<Template> {
+    Template::compile_with_options(template, &RenderOptions {
+        include_dir, valid_syntax_chars: Some(TEMPLATE_SYNTAX_CHARS), syntax: &get_template_syntax(), ..Default::default()
+    })
+        .map_err(new_err)
+}
+
+/// the environment variables, and the commands if the blog config allows them.
+/// these only look up their own syntax chars, so they come before the blog config
+pub struct BuiltinContexts {
+    env: EnvContext,
+    commands: Option<CommandContext>,
+}
+
+impl BuiltinContexts {
+    pub fn new(blog_config: &BlogConfig) -> BuiltinContexts {
+        let commands = if blog_config.template_commands.unwrap_or(false) {
+            // the paths in a command are relative to the repo, no matter where we were run from
+            let mut commands = CommandContext::new();
+            if let Some(git_root) = &blog_config.git_root {
+                commands = commands.current_dir(git_root);
+            }
+            Some(commands)
+        } else {
+            None
+        };
+        BuiltinContexts { env: EnvContext::new(), commands }
+    }
+
+    /// every `${{ key }}` is looked up in the given context
+    pub fn chain<'a>(&'a self, context: &'a dyn Context) -> ContextChain<'a> {
+        let mut chain = ContextChain::new().or(&self.env);
+        if let Some(commands) = &self.commands {
+            chain = chain.or(commands);
+        }
+        chain.or(context)
+    }
+}
+
 pub fn get_template(template: &Option<PathBuf>) -> io::Result<String> {
     let default_template = include_str!("../templates/default.html");
     match template {
@@ -414,7 +607,7 @@ pub fn get_template(template: &Option<PathBuf>) -> io::Result<String> {
     }
 }
 
-pub fn create_blog_config_interactively() -> io::Result<BlogConfig> {
+pub fn create_blog_config_interactively(git_root: &Path) -> io::Result<BlogConfig> {
     let mut config = BlogConfig::default();
     println!("These are the bare minimum options needed to create a nice looking blog");
     println!("After filling these in, the blog file will be created for you in the .git/ directory");
@@ -437,15 +630,12 @@ pub fn create_blog_config_interactively() -> io::Result<BlogConfig> {
 
     // now we want to save that to the file:
     let blog_config_json = serde_json::to_string_pretty(&config)?;
-    let git_dir = format!(".git{}", std::path::MAIN_SEPARATOR);
-    let mut blog_config_path = PathBuf::from(git_dir);
-    blog_config_path.push(DEFAULT_BLOG_CONFIG_NAME);
-    std::fs::write(blog_config_path, blog_config_json)?;
+    std::fs::write(get_default_blog_config_path(git_root), blog_config_json)?;
 
     Ok(config)
 }
 
-pub fn interactively_ask_about_blog_config() -> io::Result<BlogConfig> {
+pub fn interactively_ask_about_blog_config(git_root: &Path) -> io::Result<BlogConfig> {
     let first_choice = format!("Create a .git/{} file interactively", DEFAULT_BLOG_CONFIG_NAME);
     let mut choices = interact::InteractChoices::from(&[
         &first_choice,
@@ -456,7 +646,7 @@ pub fn interactively_ask_about_blog_config() -> io::Result<BlogConfig> {
     choices.description = Some(description.to_string());
     let selected = interact::interact_number(choices)?;
     if selected == 1 {
-        create_blog_config_interactively()
+        create_blog_config_interactively(git_root)
     } else if selected == 2 {
         Ok(BlogConfig::default())
     } else {
@@ -464,19 +654,20 @@ pub fn interactively_ask_about_blog_config() -> io::Result<BlogConfig> {
     }
 }
 
-/// it is assumed that this is ran from the root of your
-/// git directory, as such, we can find the:
-/// .git/blogconfig.json file
-pub fn get_blog_config_from_default_location() -> io::Result<BlogConfig> {
-    let git_dir = format!(".git{}", std::path::MAIN_SEPARATOR);
-    let mut search_for = PathBuf::from(git_dir);
-    search_for.push(DEFAULT_BLOG_CONFIG_NAME);
-    let file = std::fs::read_to_string(search_for)?;
+/// the .git/blogconfig.json file in the root of your git directory
+pub fn get_default_blog_config_path(git_root: &Path) -> PathBuf {
+    let mut path = git_root.join(".git");
+    path.push(DEFAULT_BLOG_CONFIG_NAME);
+    path
+}
+
+pub fn get_blog_config_from_default_location(git_root: &Path) -> io::Result<BlogConfig> {
+    let file = std::fs::read_to_string(get_default_blog_config_path(git_root))?;
     let obj: BlogConfig = serde_json::from_str(&file)?;
     Ok(obj)
 }
 
-pub fn get_blog_config(path: &Option<PathBuf>, is_interactive: bool) -> io::Result<BlogConfig> {
+pub fn get_blog_config(path: &Option<PathBuf>, is_interactive: bool, git_root: &Path) -> io::Result<BlogConfig> {
     match path {
         Some(p) => {
             let file = std::fs::read_to_string(p)?;
@@ -485,13 +676,13 @@ pub fn get_blog_config(path: &Option<PathBuf>, is_interactive: bool) -> io::Resu
         }
         // no blog config option provided, try to look
         // in default location
-        None => match get_blog_config_from_default_location() {
+        None => match get_blog_config_from_default_location(git_root) {
             Ok(obj) => Ok(obj),
             // failed to find it, so if in interactive mode, ask user
             // if they want to create one, or if not interactive, just
             // return default config
             Err(_) => match is_interactive {
-                true => interactively_ask_about_blog_config(),
+                true => interactively_ask_about_blog_config(git_root),
                 false => {
                     eprintln!("Continuing with default empty blog config");
                     Ok(BlogConfig::default())
@@ -501,6 +692,14 @@ pub fn get_blog_config(path: &Option<PathBuf>, is_interactive: bool) -> io::Resu
     }
 }
 
+pub fn parse_blog_header_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
 pub fn parse_blog_header_line(line: &str, blog_config: &mut BlogConfig) {
     let first_colon_index = if let Some(ind) = line.find(':') {
         ind
@@ -546,6 +745,21 @@ pub fn parse_blog_header_line(line: &str, blog_config: &mut BlogConfig) {
         "blog_file_name" => {
             blog_config.blog_file_name = Some(value.to_owned());
         }
+        "toc" => {
+            blog_config.toc = parse_blog_header_bool(value);
+        }
+        _ if key.starts_with("markdown.") => {
+            let enabled = if let Some(b) = parse_blog_header_bool(value) {
+                b
+            } else {
+                eprintln!("WARN: Ignoring blog header option '{}' because '{}' is not true or false", key, value);
+                return;
+            };
+            let markdown_config = blog_config.markdown.get_or_insert_with(MarkdownConfig::default);
+            if !markdown_config.set_option(&key["markdown.".len()..], enabled) {
+                eprintln!("WARN: Ignoring unknown blog header option '{}'", key);
+            }
+        }
         _ => {},
     }
 }
@@ -623,18 +837,32 @@ pub fn replace_with_valid_word(word: &str) -> String {
     out_word
 }
 
+/// turns some text into lowercase words separated by dashes.
+/// this can be empty if nothing in the text can be used in a url
+pub fn slugify(text: &str) -> String {
+    let mut out_str: String = "".into();
+    // transliterate first so that non-ascii text (eg: cyrillic, japanese)
+    // still creates a readable url. this can also split one word into
+    // several, eg: "北京" becomes "Bei Jing"
+    let text = deunicode::deunicode(text);
+    for word in text.split_whitespace() {
+        let valid_word = replace_with_valid_word(&word.to_lowercase());
+        if valid_word.is_empty() {
+            continue;
+        }
+        if !out_str.is_empty() {
+            out_str.push('-');
+        }
+        out_str.push_str(&valid_word);
+    }
+    out_str
+}
+
 pub fn get_blog_file_name(title: &Option<String>) -> Option<String> {
     if let Some(title) = title {
-        let mut out_str: String = "".into();
-        for word in title.split_whitespace() {
-            let valid_word = replace_with_valid_word(&word.to_lowercase());
-            if valid_word.is_empty() {
-                continue;
-            }
-            if !out_str.is_empty() {
-                out_str.push('-');
-            }
-            out_str.push_str(&valid_word);
+        let mut out_str = slugify(title);
+        if out_str.is_empty() {
+            out_str = DEFAULT_BLOG_FILE_NAME.into();
         }
         Some(out_str)
     } else {
@@ -650,20 +878,23 @@ pub fn get_date_string_from_timestamp(timestamp: i64) -> (String, String) {
     (human_date, iso)
 }
 
+/// the author and date line that goes under the title. if we dont know
+/// the author or the date, that part is left out instead of being
+/// filled with a placeholder
 pub fn get_name_and_date_html(blog_info: &BlogConfig) -> String {
-    let name_url = match &blog_info.author_url {
-        Some(s) => s,
-        None => "#",
-    };
-    let name = match &blog_info.author_name {
-        Some(s) => s,
-        None => "AUTHORNAMENOTFOUND",
+    let name_html = match (&blog_info.author_name, &blog_info.author_url) {
+        (Some(name), Some(url)) => format!("<a style=\"font-weight: bold; color: #92979b\" href=\"{}\">{}</a>",
+            escape_html_attribute(url), escape_html_text(name)),
+        (Some(name), None) => format!("<span style=\"font-weight: bold\">{}</span>", escape_html_text(name)),
+        (None, _) => "".into(),
     };
-    let human_date = match &blog_info.date_written {
-        Some(s) => s,
-        None => "DATESTRINGNOTFOUND",
+    let byline = match (name_html.is_empty(), &blog_info.date_written) {
+        (true, None) => return "".into(),
+        (false, None) => name_html,
+        (true, Some(date)) => escape_html_text(date),
+        (false, Some(date)) => format!("{} - {}", name_html, escape_html_text(date)),
     };
-    format!("<span style=\"color: #92979b; font-size: 16px\"><a style=\"font-weight: bold; color: #92979b\" href=\"{}\">{}</a> - {}</span>", name_url, name, human_date)
+    format!("<span class=\"byline\" style=\"color: #92979b; font-size: 16px\">{}</span>", byline)
 }
 
 pub fn get_about_me_markdown(blog_info: &BlogConfig) -> String {
@@ -694,6 +925,16 @@ pub fn get_about_me_markdown(blog_info: &BlogConfig) -> String {
     }
 }
 
+pub fn get_about_me_html(blog_info: &BlogConfig) -> String {
+    let about_me = get_about_me_markdown(blog_info);
+    if about_me.is_empty() {
+        return about_me;
+    }
+    let mut html_out = String::from("");
+    pulldown_cmark::html::push_html(&mut html_out, pulldown_cmark::Parser::new(&about_me));
+    html_out
+}
+
 /// for a given blog file, read its header
 /// and apply its blog config to the global blog config.
 /// This returned new blog config should have everything
@@ -709,6 +950,9 @@ pub fn get_applied_blog_config<'a>(
     // and then apply the blog information of this blog file
     // onto the cloned config
     this_blog_info.apply(blog_info);
+    if let Some(s) = &updated_blog.blog_file_name {
+        this_blog_info.blog_file_name = Some(s.clone());
+    }
 
     // some validation: title is required, and
     // we should have at least one entry in the updated blog commits
@@ -733,7 +977,12 @@ pub fn get_applied_blog_config<'a>(
     if this_blog_info.blog_file_name.is_none() {
         this_blog_info.blog_file_name = get_blog_file_name(&this_blog_info.title);
     }
-    if this_blog_info.author_name.is_none() {
+    let markdown_config = this_blog_info.markdown.clone().unwrap_or_default();
+    let word_count = get_word_count(rest_of_blog_file, &markdown_config);
+    this_blog_info.word_count = Some(word_count);
+    this_blog_info.reading_time_minutes = Some(get_reading_time_minutes(word_count));
+    this_blog_info.blog_post_path = this_blog_info.get_blog_post_path(updated_blog)?;
+    if this_blog_info.author_name.is_none() && !updated_blog.git_author_name.is_empty() {
         this_blog_info.author_name = Some(updated_blog.git_author_name.clone());
     }
     if this_blog_info.date_written.is_none() {
@@ -757,88 +1006,134 @@ pub fn get_applied_blog_config<'a>(
 pub fn render_blog_actual(
     blog_file: &str,
     updated_blog: &BlogFile,
-    template: &str,
+    template: &Template,
+    linkable_files: &HashSet<String>,
     blog_config: &mut BlogConfig, // TODO: doesnt need to be mutable
-) -> io::Result<(String, String, String)> {
+) -> io::Result<(String, Vec<Diagnostic>, PathBuf, Vec<BlogAsset>)> {
     let (this_blog_info, rest_of_blog_file) = get_applied_blog_config(
         blog_file, updated_blog, blog_config)?;
 
-    // we append some text to the markdown string before we render it
-    // to html. this includes the blog name and date, the title,
-    // and an about me section (depending if user supplied necessary information for about me)
-    let blog_name_and_date = get_name_and_date_html(&this_blog_info);
-    let about_me = get_about_me_markdown(&this_blog_info);
-    let use_title = if let Some(t) = &this_blog_info.title { format!("# {}", t) } else { "# title".into() };
-    let render_this = format!("{}\n{}\n{}\n\n\n{}", use_title, blog_name_and_date, rest_of_blog_file, about_me);
+    // the byline and the about me section are their own template keys
+    // so that the template decides where they go. with the legacy layout
+    // they are also baked into the rendered_markdown like they used to be:
+    // the title, the blog name and date, the body, and then the about me section
+    let byline_html = get_name_and_date_html(&this_blog_info);
+    let about_me_html = get_about_me_html(&this_blog_info);
+    let legacy_layout = this_blog_info.legacy_layout.unwrap_or(false);
+    let render_this = if legacy_layout {
+        let about_me = get_about_me_markdown(&this_blog_info);
+        let use_title = if let Some(t) = &this_blog_info.title { format!("# {}", t) } else { "# title".into() };
+        format!("{}\n{}\n\n{}\n\n\n{}", use_title, byline_html, rest_of_blog_file, about_me)
+    } else {
+        rest_of_blog_file.to_owned()
+    };
 
     // now we should have all the information we need
     // we first create an html string from the rest of the markdown text
-    // after we removed the blog header:
-    let parser = Parser::new(&render_this);
-    let mut html_out = String::from("");
-    html::push_html(&mut html_out, parser);
+    // after we removed the blog header. any local files it links to
+    // will be copied next to the rendered blog post:
+    let asset_context = AssetContext {
+        blog_path_from_root: &updated_blog.path_from_root,
+        blog_post_path: this_blog_info.blog_post_path.as_deref().unwrap_or(""),
+        blog_home_url: this_blog_info.blog_home_url.as_deref().unwrap_or(""),
+        linkable_files,
+    };
+    let markdown_config = this_blog_info.markdown.clone().unwrap_or_default();
+    let rendered_markdown = render_markdown(&render_this, &markdown_config, &asset_context);
+    let rendered_body = if legacy_layout {
+        render_markdown(rest_of_blog_file, &markdown_config, &asset_context).html
+    } else {
+        rendered_markdown.html.clone()
+    };
+    let toc_html = if this_blog_info.toc.unwrap_or(false) {
+        get_toc_html(&rendered_markdown.headings)
+    } else {
+        "".into()
+    };
 
     // then we transclude the blog information and the rendered markdown
     // into the template:
-    let mut warnings: String = "".into();
-    let markdown_rendered = Some(html_out);
-    let replace_context = this_blog_info.to_hashmap_context(&markdown_rendered);
-    let transcluded = replace_all_from_ex(
-        &template, &replace_context, FailureModeEx::FM_callback(|key| {
-            warnings.push_str(&format!("Failed to find key '{}'\n", key));
-            Some("".into())
-        }), None);
-    Ok((transcluded, warnings, this_blog_info.blog_file_name.unwrap_or("MISSINGFILEFORSOMEREASON".into())))
+    let markdown_rendered = Some(rendered_markdown.html);
+    let post_context = this_blog_info.to_context(&markdown_rendered);
+    let mut replace_context = Scope::new(&post_context);
+    replace_context.insert("toc_html", toc_html);
+    replace_context.insert("byline_html", byline_html);
+    replace_context.insert("about_me_html", about_me_html);
+    replace_context.insert("rendered_body", rendered_body);
+    let builtin_contexts = BuiltinContexts::new(&this_blog_info);
+    let transcluded = template.render_with_diagnostics(
+        &RawHtmlKeys { context: &builtin_contexts.chain(&replace_context), raw_keys: HTML_TEMPLATE_KEYS },
+        FailureMode::FM_default("".into()),
+        &RenderOptions { html_escape: true, valid_syntax_chars: Some(TEMPLATE_SYNTAX_CHARS), ..Default::default() });
+    let outfilename = this_blog_info.get_blog_post_output_path()
+        .unwrap_or_else(|| PathBuf::from("MISSINGFILEFORSOMEREASON"));
+    Ok((transcluded.text, transcluded.diagnostics, outfilename, rendered_markdown.assets))
 }
 
 pub fn render_blogpost_link(
     this_post_config: &BlogConfig,
-    template: &str,
-) -> io::Result<(String, String)> {
+    template: &Template,
+) -> (String, Vec<Diagnostic>) {
     // the empty string would be the markdown content, but thats only used
     // for rendering the actual blog file, whereas here we are just rendering the html element
     // for this blog post that will go into the blog homepage.
     let dumref = Some("".into());
-    let mut warnings: String = "".into();
-    let replace_context = this_post_config.to_hashmap_context(&dumref);
-    let transcluded = replace_all_from_ex(
-        template, &replace_context, FailureModeEx::FM_callback(|key| {
-            warnings.push_str(&format!("{}, ", key));
-            Some("".into())
-        }), None);
-    Ok((transcluded, warnings))
+    let replace_context = this_post_config.to_context(&dumref);
+    let builtin_contexts = BuiltinContexts::new(this_post_config);
+    let transcluded = template.render_with_diagnostics(
+        &RawHtmlKeys { context: &builtin_contexts.chain(&replace_context), raw_keys: HTML_TEMPLATE_KEYS },
+        FailureMode::FM_default("".into()),
+        &RenderOptions { html_escape: true, valid_syntax_chars: Some(TEMPLATE_SYNTAX_CHARS), ..Default::default() });
+    (transcluded.text, transcluded.diagnostics)
 }
 
 /// blog_post_links_html is the html string for all of the blog post links, and
 /// links_html is the html string of all of the links to your various social media
 /// github profile, etc...
+/// the homepage template can also loop over the blog posts and the links itself:
+/// `posts` is a list of every post with the same keys as the blog post link template
+/// (eg: `posts.0.title`), and `links` is a list of links with a `url` and a `name`
 pub fn render_blog_homepage(
     global_blog_config: &BlogConfig,
+    posts: &[BlogConfig],
     blog_post_links_html: &str,
-    links_html: &str,
-    template: &str
-) -> io::Result<(String, String)> {
+    template: &str,
+    include_dir: Option<&Path>,
+) -> io::Result<(String, Vec<Diagnostic>)> {
     let dumref = Some("".into());
-    let mut warnings: String = "".into();
-    let mut replace_context = global_blog_config.to_hashmap_context(&dumref);
-    replace_context.insert("blog_post_links_html", blog_post_links_html.into());
-    replace_context.insert("links_html", links_html.into());
-    let transcluded = replace_all_from_ex(
-        template, &replace_context, FailureModeEx::FM_callback(|key| {
-            warnings.push_str(&format!("{}, ", key));
-            Some("".into())
-        }), None);
-    Ok((transcluded, warnings))
+    let site_context = global_blog_config.to_context(&dumref);
+    let mut replace_context = Scope::new(&site_context);
+    replace_context.insert("blog_post_links_html", blog_post_links_html);
+    replace_context.insert("links_html", get_links_html(global_blog_config));
+    let links: Vec<Value> = get_links(global_blog_config).into_iter().map(|(url, name)| {
+        let mut link = Value::default();
+        link.insert("url", url);
+        link.insert("name", name);
+        link
+    }).collect();
+    replace_context.insert("links", links);
+    let posts: Vec<Value> = posts.iter().map(|post| post.to_context(&None)).collect();
+    replace_context.insert("posts", posts);
+    let builtin_contexts = BuiltinContexts::new(global_blog_config);
+    let transcluded = render_template_with_diagnostics(
+        template, &RawHtmlKeys { context: &builtin_contexts.chain(&replace_context), raw_keys: HTML_TEMPLATE_KEYS },
+        FailureMode::FM_default("".into()),
+        &RenderOptions {
+            include_dir, html_escape: true, valid_syntax_chars: Some(TEMPLATE_SYNTAX_CHARS),
+            syntax: &get_template_syntax(), ..Default::default()
+        }).map_err(new_err)?;
+    Ok((transcluded.text, transcluded.diagnostics))
 }
 
 pub fn render_blog_to_string(
     updated_blog: &BlogFile,
-    template: &str,
+    template: &Template,
+    linkable_files: &HashSet<String>,
     main_ref_branch_name: &str,
     blog_config: &mut BlogConfig,
-) -> io::Result<(String, String, String)> {
+) -> io::Result<(String, Vec<Diagnostic>, PathBuf, Vec<BlogAsset>)> {
     let blog_file = get_blog_file_from_branch(&updated_blog.path_from_root, &main_ref_branch_name)?;
-    let out = render_blog_actual(&blog_file, updated_blog, template, blog_config)?;
+    let out = render_blog_actual(&blog_file, updated_blog, template, linkable_files, blog_config)?;
     Ok(out)
 }
 
@@ -858,6 +1153,7 @@ pub fn generate_and_write_rss_file(
                 written: 0,
                 updated: 0,
                 git_author_name: "".into(),
+                blog_file_name: None,
             };
             match generate_rss(&blog_config, &dummy_file, &rss_items_xml) {
                 Ok(rss_string) => {
@@ -873,62 +1169,214 @@ pub fn generate_and_write_rss_file(
     }
 }
 
-pub fn get_links_html(blog_config: &BlogConfig) -> String {
-    let mut out_str = "".into();
+pub fn generate_and_write_sitemap_file(
+    skipping_sitemap_error_message: Option<String>,
+    blog_config: &BlogConfig,
+    homepage_lastmod: &Option<String>,
+    sitemap_items_xml: &str,
+    robots_txt: bool,
+    output_path: PathBuf,
+) {
+    if let Some(err_msg) = skipping_sitemap_error_message {
+        eprintln!("Skipping sitemap generation because {}", err_msg);
+        return;
+    }
+    match generate_sitemap(blog_config, homepage_lastmod, sitemap_items_xml) {
+        Ok(sitemap_string) => {
+            let mut outpath = output_path.clone();
+            outpath.push(SITEMAP_ENDING);
+            if let Err(e) = std::fs::write(&outpath, sitemap_string) {
+                eprintln!("Failed to write sitemap string to file: {}", e);
+                return;
+            }
+        }
+        Err(e) => {
+            eprintln!("Skipping sitemap generation because {}", e);
+            return;
+        }
+    }
+
+    // robots.txt only makes sense if we successfully made a sitemap
+    // for it to reference
+    if robots_txt {
+        match generate_robots_txt(blog_config) {
+            Ok(robots_string) => {
+                let mut outpath = output_path;
+                outpath.push(ROBOTS_ENDING);
+                if let Err(e) = std::fs::write(&outpath, robots_string) {
+                    eprintln!("Failed to write robots.txt to file: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Skipping robots.txt generation because {}", e)
+        }
+    }
+}
+
+/// the stylesheet for syntax highlighted code blocks. this is written
+/// every time (rather than only when a blog post changed) because its
+/// shared by all of the blog posts
+pub fn write_highlight_stylesheet(blog_config: &BlogConfig, output_path: PathBuf) -> io::Result<()> {
+    let markdown_config = blog_config.markdown.clone().unwrap_or_default();
+    if !markdown_config.syntax_highlighting.unwrap_or(true) {
+        return Ok(());
+    }
+    let theme_name = blog_config.syntax_theme.as_deref().unwrap_or(DEFAULT_HIGHLIGHT_THEME);
+    let css = get_highlight_css(theme_name).map_err(new_err)?;
+    if !output_path.exists() {
+        std::fs::create_dir_all(&output_path)
+            .map_err(|_| new_err(format!("Failed to create temporary directory: {:?}", output_path)))?;
+    }
+    let mut outpath = output_path;
+    outpath.push(HIGHLIGHT_CSS_ENDING);
+    std::fs::write(&outpath, css)
+        .map_err(|_| new_err(format!("Failed to write syntax highlighting stylesheet: {:?}", outpath)))
+}
+
+/// the (url, name) of the links that go on the homepage
+pub fn get_links(blog_config: &BlogConfig) -> Vec<(String, &'static str)> {
     let mut links_list = vec![];
     let blog_home_url = match &blog_config.blog_home_url {
         Some(s) => s,
-        None => return out_str,
+        None => return links_list,
     };
 
     if let Some(s) = &blog_config.author_url {
-        links_list.push((s, "Homepage"));
+        links_list.push((s.clone(), "Homepage"));
     }
     if let Some(s) = &blog_config.author_projects_url {
-        links_list.push((s, "Code"));
+        links_list.push((s.clone(), "Code"));
     }
     let rss_link = format!("{}/{}", blog_home_url, rss::RSS_ENDING);
-    links_list.push((&rss_link, "RSS"));
+    links_list.push((rss_link, "RSS"));
+    links_list
+}
+
+pub fn get_links_html(blog_config: &BlogConfig) -> String {
+    let mut out_str = "".into();
 
     // TODO: how to add user specified links?
     // easiest answer is for them to just manually edit their
     // homepage template and to not use the ${{ links_html }} at all
     // but maybe theres a better way
-    for link in links_list {
-        let link_html = format!("<a href=\"{}\" class=\"link\">{}</a>", link.0, link.1);
+    for link in get_links(blog_config) {
+        let link_html = format!("<a href=\"{}\" class=\"link\">{}</a>", escape_html_attribute(&link.0), link.1);
         out_str = format!("{}{}\n", out_str, link_html);
     }
 
     out_str
 }
 
+/// for every blog file, figure out what its blog_file_name is, and make sure
+/// that no two blog files will be written to the same url. if they are, then
+/// depending on the on_slug_collision setting, either error, or add a suffix
+/// to the blog_file_name. the blog files are sorted by when they were written
+/// so the oldest blog file keeps its url, and the suffixes are deterministic.
+/// returns the final mapping of blog file path to url path
+pub fn resolve_blog_file_names(
+    all_blog_files: &mut [BlogFile],
+    blog_config: &BlogConfig,
+    main_ref_branch_name: &str,
+) -> io::Result<Vec<(String, String)>> {
+    all_blog_files.sort_by(|a, b| a.written.cmp(&b.written)
+        .then_with(|| a.path_from_root.cmp(&b.path_from_root)));
+    let on_slug_collision = blog_config.on_slug_collision.unwrap_or_default();
+
+    // url path -> the blog file that is using it
+    let mut used_paths: HashMap<String, String> = HashMap::new();
+    let mut url_mapping = vec![];
+    for blog_file in all_blog_files.iter_mut() {
+        let blog_text = get_blog_file_from_branch(&blog_file.path_from_root, main_ref_branch_name)?;
+        let (mut blog_info, _) = get_applied_blog_config(&blog_text, blog_file, blog_config)?;
+        let base_name = blog_info.blog_file_name.clone().unwrap_or_else(|| DEFAULT_BLOG_FILE_NAME.into());
+        let original_path = blog_info.blog_post_path.clone().unwrap_or_default();
+        let mut blog_post_path = original_path.clone();
+        let mut suffix = 1;
+        while let Some(other_blog) = used_paths.get(&blog_post_path) {
+            if on_slug_collision == SlugCollision::Error {
+                let err_msg = format!("Blog files {} and {} would both be written to '{}'.\nSet a different blog_file_name in the header of one of them", other_blog, blog_file.path_from_root, blog_post_path);
+                return Err(new_err(err_msg));
+            }
+            suffix += 1;
+            blog_info.blog_file_name = Some(get_suffixed_blog_file_name(&base_name, suffix));
+            blog_post_path = blog_info.get_blog_post_path(blog_file)?.unwrap_or_default();
+        }
+        if suffix > 1 {
+            eprintln!("WARN: Blog file {} would have been written to the same url as {}. Using '{}' instead", blog_file.path_from_root, used_paths[&original_path], blog_post_path);
+        }
+
+        used_paths.insert(blog_post_path.clone(), blog_file.path_from_root.clone());
+        blog_file.blog_file_name = blog_info.blog_file_name;
+        url_mapping.push((blog_file.path_from_root.clone(), blog_post_path));
+    }
+
+    Ok(url_mapping)
+}
+
+/// copies the files that a blog post links to from git into
+/// the output directory. a missing file is not fatal because it was
+/// probably just a link to something that isnt in the repository
+pub fn write_blog_assets(
+    assets: &[BlogAsset],
+    main_ref_branch_name: &str,
+    output_path: &Path,
+) {
+    for asset in assets {
+        let data = match get_file_bytes_from_branch(&asset.path_from_root, main_ref_branch_name) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("WARN: Failed to copy linked file: {}", e);
+                continue;
+            }
+        };
+        let mut outpath = output_path.to_path_buf();
+        outpath.push(&asset.output_path);
+        if let Some(parent) = outpath.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                eprintln!("WARN: Failed to create directory {:?} for linked file: {}", parent, e);
+                continue;
+            }
+        }
+        if let Err(e) = std::fs::write(&outpath, data) {
+            eprintln!("WARN: Failed to write linked file {:?}: {}", outpath, e);
+        }
+    }
+}
+
 pub fn render_and_output_blog_files(
     blog_template_path: &Option<PathBuf>,
     blog_config: &mut BlogConfig,
     main_ref_branch_name: &str,
     output_path: PathBuf,
-    blog_file_name: &str,
+    all_blog_files: &[BlogFile],
     blog_branch_name: &str,
+    strict: bool,
 ) -> io::Result<()> {
     let updated_blogs = get_all_blog_files_changed_since_last_blog_update(
-        blog_branch_name, &main_ref_branch_name, blog_file_name)?;
-    let template = get_template(blog_template_path)?;
-
-    for updated_blog in &updated_blogs {
-        let (rendered, _warnings, outfilename) = render_blog_to_string(
-            updated_blog, &template, &main_ref_branch_name, blog_config)?;
-        // TODO: this is kind of verbose.. idk if i want to output this for every blog file...
-        // if !warnings.is_empty() {
-        //     eprintln!("Found some warnings while transcluding the markdown text into the html template:\n{}", warnings);
-        // }
+        blog_branch_name, &main_ref_branch_name, all_blog_files)?;
+    let template_name = get_template_name(blog_template_path, "default.html");
+    let include_dir = get_template_include_dir(blog_template_path);
+    let template = compile_template(&get_template(blog_template_path)?, include_dir.as_deref())?;
+    set_default_layout(blog_config, &template, blog_template_path);
+    // links to other blog posts, or to files that arent in the repository, are left as they are
+    let blog_paths: HashSet<&str> = all_blog_files.iter().map(|f| f.path_from_root.as_str()).collect();
+    let linkable_files = get_all_files_of_ref(main_ref_branch_name, |path| blog_paths.contains(path))?;
+
+    for updated_blog in updated_blogs {
+        let (rendered, diagnostics, outfilename, assets) = render_blog_to_string(
+            updated_blog, &template, &linkable_files, &main_ref_branch_name, blog_config)?;
+        check_template_diagnostics(&diagnostics, &template_name, &updated_blog.path_from_root, strict)?;
         let mut outpath = output_path.clone();
-        if !outpath.exists() {
-            std::fs::create_dir_all(&outpath)
-                .map_err(|_| new_err(format!("Failed to create temporary directory: {:?}", outpath)))?;
-        }
         outpath.push(outfilename);
+        // depending on the url style, the blog post might be in its own directory
+        if let Some(parent) = outpath.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(&parent)
+                    .map_err(|_| new_err(format!("Failed to create temporary directory: {:?}", parent)))?;
+            }
+        }
         std::fs::write(&outpath, rendered)
             .map_err(|_| new_err(format!("Failed to write blog file: {:?}", outpath)))?;
+        write_blog_assets(&assets, main_ref_branch_name, &output_path);
     }
     Ok(())
 }
@@ -939,17 +1387,27 @@ pub fn render_and_output_homepage_and_rss(
     blog_config: &BlogConfig,
     main_ref_branch_name: &str,
     output_path: PathBuf,
-    blog_file_name: &str,
+    all_blog_files: &[BlogFile],
     no_rss: bool,
+    no_sitemap: bool,
+    robots_txt: bool,
+    strict: bool,
 ) -> io::Result<()> {
-    let blog_post_link_template = get_blog_post_link_template(&blogpost_link_template)?;
-    let mut all_tracked_blogfiles = get_all_blog_files_ever(&main_ref_branch_name, blog_file_name)?;
+    let blog_post_link_include_dir = get_template_include_dir(blogpost_link_template);
+    let blog_post_link_template = compile_template(
+        &get_blog_post_link_template(&blogpost_link_template)?, blog_post_link_include_dir.as_deref())?;
+    let blog_post_link_template_name = get_template_name(blogpost_link_template, "default-blogpost-link.html");
+    let mut posts = vec![];
+    let mut all_tracked_blogfiles: Vec<&BlogFile> = all_blog_files.iter().collect();
     let mut blog_post_links_html = "".into();
     let mut rss_items_xml = "".into();
     let mut skipping_rss_error_message = if !no_rss { None } else { Some("user requested no RSS".into()) };
+    let mut sitemap_items_xml = "".into();
+    let mut homepage_lastmod: Option<String> = None;
+    let mut skipping_sitemap_error_message = if !no_sitemap { None } else { Some("user requested no sitemap".into()) };
 
     all_tracked_blogfiles.sort_by(|a, b| b.written.cmp(&a.written));
-    for blog_file in &all_tracked_blogfiles {
+    for blog_file in all_tracked_blogfiles {
         let blog_text = get_blog_file_from_branch(&blog_file.path_from_root, &main_ref_branch_name)?;
         let (blog_info, _) = get_applied_blog_config(&blog_text, blog_file, &blog_config)?;
 
@@ -964,25 +1422,50 @@ pub fn render_and_output_homepage_and_rss(
             }
         }
 
-        // TODO: should log warnings? could be quite verbose...
-        let (blog_post_link, _warnings) = render_blogpost_link(&blog_info, &blog_post_link_template)?;
+        if skipping_sitemap_error_message.is_none() {
+            match generate_sitemap_item(&blog_info, blog_file) {
+                Ok(sitemap_item_xml_string) => {
+                    sitemap_items_xml = format!("{}{}\n", sitemap_items_xml, sitemap_item_xml_string);
+                }
+                Err(err_msg) => {
+                    skipping_sitemap_error_message = Some(err_msg);
+                }
+            }
+            // the homepage was last modified whenever the most
+            // recent blog post was modified
+            let lastmod = get_lastmod(&blog_info, blog_file);
+            if lastmod > homepage_lastmod {
+                homepage_lastmod = lastmod;
+            }
+        }
+
+        let (blog_post_link, diagnostics) = render_blogpost_link(&blog_info, &blog_post_link_template);
+        check_template_diagnostics(&diagnostics, &blog_post_link_template_name, &blog_file.path_from_root, strict)?;
         blog_post_links_html = format!("{}{}\n", blog_post_links_html, blog_post_link);
+        posts.push(blog_info);
     }
-    let links_html = get_links_html(&blog_config);
     // now we have the html string of the list of blog posts, we will
     // transclude that into the blog homepage html template:
+    let homepage_include_dir = get_template_include_dir(blog_homepage_template);
+    let homepage_template_name = get_template_name(blog_homepage_template, "default-homepage.html");
     let blog_homepage_template = get_blog_homepage_template(blog_homepage_template)?;
-    let (rendered_homepage, warnings) = render_blog_homepage(&blog_config, &blog_post_links_html, &links_html, &blog_homepage_template)?;
+    let (rendered_homepage, diagnostics) = render_blog_homepage(
+        &blog_config, &posts, &blog_post_links_html,
+        &blog_homepage_template, homepage_include_dir.as_deref())?;
+    check_template_diagnostics(&diagnostics, &homepage_template_name, "the homepage", strict)?;
     let mut outpath = PathBuf::from(output_path.clone());
     outpath.push("index.html");
     std::fs::write(&outpath, rendered_homepage)
         .map_err(|_| new_err("Failed to write blog homepage"))?;
 
-    eprintln!("WARN: The following keys were not found when trying to render the homepage.\nPlease check your homepage to make sure it looks correct. Otherwise, fill in these missing keys in your blog config:\n{}\n", warnings);
-
     // now render the RSS (if successful and not skipped)
     generate_and_write_rss_file(
         skipping_rss_error_message, &blog_config, &rss_items_xml, output_path.clone());
+
+    // and the sitemap (and optionally robots.txt) from the same blog posts
+    generate_and_write_sitemap_file(
+        skipping_sitemap_error_message, &blog_config, &homepage_lastmod,
+        &sitemap_items_xml, robots_txt, output_path.clone());
     Ok(())
 }
 
@@ -1073,13 +1556,11 @@ pub fn perform_blog_branch_update(
 }
 
 pub fn run_cli(cli: Cli) -> io::Result<()> {
-    // before we change to the repo's root,
-    // we want to reset all the potential paths that the user set
-    // to their absolute paths:
-    let cli = set_paths_to_absolute(cli)?;
-
+    // the paths that the user set are relative to where they ran this,
+    // but the output directory is relative to the repo's root:
+    let mut cli = set_paths_to_absolute(cli)?;
     let git_root = get_git_toplevel_absolute_path()?;
-    std::env::set_current_dir(&git_root)?;
+    cli.output = git_root.join(&cli.output);
     let branch_list = get_all_git_branches()?;
     let main_ref_branch = get_main_reference_branch(&cli, &branch_list)?;
 
@@ -1099,11 +1580,19 @@ pub fn run_cli(cli: Cli) -> io::Result<()> {
         cli.blogs_branch_name
     };
 
-    let mut blog_config = get_blog_config(&cli.blog_config, !cli.no_interactive)?;
+    let mut blog_config = get_blog_config(&cli.blog_config, !cli.no_interactive, &git_root)?;
+    blog_config.git_root = Some(git_root.clone());
+
+    // before rendering anything, we need to know where every blog file
+    // is going to end up, otherwise two blog files could overwrite each other
+    let mut all_blog_files = get_all_blog_files_ever(&main_ref_branch, &cli.blog_file_name)?;
+    let url_mapping = resolve_blog_file_names(&mut all_blog_files, &blog_config, &main_ref_branch)?;
+    write_highlight_stylesheet(&blog_config, cli.output.clone())?;
+
     render_and_output_blog_files(
         &cli.blog_template, &mut blog_config,
         &main_ref_branch, cli.output.clone(),
-        &cli.blog_file_name, &blogs_branch_name
+        &all_blog_files, &blogs_branch_name, cli.strict,
     )?;
 
     // now that we rendered the individual blogs that were updated
@@ -1112,12 +1601,16 @@ pub fn run_cli(cli: Cli) -> io::Result<()> {
     render_and_output_homepage_and_rss(
         &cli.blog_post_link_template, &cli.blog_homepage_template,
         &blog_config, &main_ref_branch, cli.output.clone(),
-        &cli.blog_file_name, cli.no_rss
+        &all_blog_files, cli.no_rss,
+        cli.no_sitemap, cli.robots_txt, cli.strict,
     )?;
 
-    let mut outpath = git_root;
-    outpath.push(cli.output.clone());
-    println!("Successfully created rendered blogs in {:?}", outpath);
+    println!("Successfully created rendered blogs in {:?}", cli.output);
+    let blog_home_url = blog_config.blog_home_url.as_deref().unwrap_or("");
+    println!("Blog files are available at:");
+    for (path_from_root, blog_post_path) in &url_mapping {
+        println!("  {} -> {}/{}", path_from_root, blog_home_url, blog_post_path);
+    }
 
     let should_update = should_update(cli.yes_update, cli.no_update, cli.no_interactive)?;
     if should_update {
@@ -1129,15 +1622,11 @@ pub fn run_cli(cli: Cli) -> io::Result<()> {
 }
 
 pub fn real_main() -> io::Result<()> {
-    let current_dir = std::env::current_dir()?;
     let opts = <Cli as Options>::parse_args_default_or_exit();
     if let Err(e) = run_cli(opts) {
         eprintln!("{}", e);
-        // no matter what, go back to the directory we were at the start
-        let _ = std::env::set_current_dir(current_dir);
         std::process::exit(1);
     }
-    std::env::set_current_dir(current_dir)?;
 
     Ok(())
 }
@@ -1216,11 +1705,12 @@ mod tests {
             updated: 1621897682,
             written: 1621897682,
             git_author_name: "me".into(),
+            blog_file_name: None,
         };
         let mut blog_config = BlogConfig::default();
         blog_config.tags = Some(vec!["abcxyz".into()]);
-        let (rendered, _, _) = render_blog_actual(
-            &data, &blog_file_info, &template, &mut blog_config)?;
+        let (rendered, _, _, _) = render_blog_actual(
+            &data, &blog_file_info, &compile_template(&template, None)?, &HashSet::new(), &mut blog_config)?;
         println!("\n{}\n", rendered);
 
         let expected_tag = "<meta property=\"article:tag\" content=\"abcxyz\">";
@@ -1236,4 +1726,442 @@ mod tests {
     fn markdowntest2() {
         markdowntest2_actual().unwrap();
     }
+
+    #[test]
+    fn sitemap_uses_modified_time_or_falls_back_to_commit_time() {
+        let blog_file_info = BlogFile {
+            path_from_root: "doesntmatter".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "me".into(),
+            blog_file_name: None,
+        };
+        let mut blog_config = BlogConfig::default();
+        blog_config.blog_home_url = Some("https://example.com".into());
+        blog_config.blog_post_path = Some("a-b".into());
+        let item = generate_sitemap_item(&blog_config, &blog_file_info).unwrap();
+        assert!(item.contains("<loc>https://example.com/a-b</loc>"));
+        assert!(item.contains("<lastmod>2021-05-24"));
+
+        blog_config.modified_time_iso = Some("2022-01-01T00:00:00+00:00".into());
+        let item = generate_sitemap_item(&blog_config, &blog_file_info).unwrap();
+        assert!(item.contains("<lastmod>2022-01-01T00:00:00+00:00</lastmod>"));
+
+        let sitemap = generate_sitemap(&blog_config, &Some("2022-01-01T00:00:00+00:00".into()), &item).unwrap();
+        assert!(sitemap.contains("<loc>https://example.com/</loc>"));
+        let robots = generate_robots_txt(&blog_config).unwrap();
+        assert!(robots.contains("Sitemap: https://example.com/sitemap.xml"));
+    }
+
+    #[test]
+    fn url_style_changes_output_path_and_links() {
+        let data = std::fs::read_to_string("test/m2.md").unwrap();
+        let blog_file_info = BlogFile {
+            path_from_root: "doesntmatter".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "me".into(),
+            blog_file_name: None,
+        };
+        let template = "${{ blog_post_path }}";
+        let mut blog_config = BlogConfig::default();
+        let (rendered, _, outpath, _) = render_blog_actual(
+            &data, &blog_file_info, &Template::compile(template).unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        assert_eq!(rendered, "m2title");
+        assert_eq!(outpath, PathBuf::from("m2title"));
+
+        blog_config.url_style = Some(UrlStyle::Html);
+        let (rendered, _, outpath, _) = render_blog_actual(
+            &data, &blog_file_info, &Template::compile(template).unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        assert_eq!(rendered, "m2title.html");
+        assert_eq!(outpath, PathBuf::from("m2title.html"));
+
+        blog_config.url_style = Some(UrlStyle::Directory);
+        let (rendered, _, outpath, _) = render_blog_actual(
+            &data, &blog_file_info, &Template::compile(template).unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        assert_eq!(rendered, "m2title/");
+        assert_eq!(outpath, PathBuf::from("m2title/index.html"));
+
+        // without a blog_home_url, the stylesheet is found relative to the blog post
+        let default_template = compile_template(&get_template(&None).unwrap(), None).unwrap();
+        let (rendered, _, _, _) = render_blog_actual(
+            &data, &blog_file_info, &default_template, &HashSet::new(), &mut blog_config).unwrap();
+        assert!(rendered.contains("href=\"../highlight.css\""));
+        blog_config.blog_home_url = Some("https://example.com".into());
+        let (rendered, _, _, _) = render_blog_actual(
+            &data, &blog_file_info, &default_template, &HashSet::new(), &mut blog_config).unwrap();
+        assert!(rendered.contains("href=\"https://example.com/highlight.css\""));
+    }
+
+    #[test]
+    fn permalink_patterns_can_use_dates_and_directories() {
+        // 1621897682 is May 24, 2021
+        let expanded = expand_permalink("/{year}/{month}/{slug}", "my-post", "BLOG.md", 1621897682).unwrap();
+        assert_eq!(expanded, "2021/05/my-post");
+        let expanded = expand_permalink("/{dir}/{slug}", "my-post", "posts/rust/BLOG.md", 1621897682).unwrap();
+        assert_eq!(expanded, "posts/rust/my-post");
+        // a blog file at the root of the repo has an empty {dir}
+        let expanded = expand_permalink("/{dir}/{slug}", "my-post", "BLOG.md", 1621897682).unwrap();
+        assert_eq!(expanded, "my-post");
+        assert!(expand_permalink("/{nope}/{slug}", "my-post", "BLOG.md", 1621897682).is_err());
+
+        assert_eq!(get_output_path_from_url_path("2021/05/my-post/"), PathBuf::from("2021/05/my-post/index.html"));
+        assert_eq!(get_output_path_from_url_path("posts/my-post.html"), PathBuf::from("posts/my-post.html"));
+        assert_eq!(get_root_path_from_url_path("2021/05/my-post/"), "../../..");
+        assert_eq!(get_root_path_from_url_path("posts/my-post.html"), "..");
+        assert_eq!(get_root_path_from_url_path("my-post.html"), ".");
+    }
+
+    #[test]
+    fn blog_file_names_are_transliterated() {
+        assert_eq!(get_blog_file_name(&Some("Привет мир".into())), Some("privet-mir".into()));
+        assert_eq!(get_blog_file_name(&Some("Hello, World!".into())), Some("hello-world".into()));
+        // there should always be something to use in the url
+        assert_eq!(get_blog_file_name(&Some("!!!".into())), Some(DEFAULT_BLOG_FILE_NAME.into()));
+        let japanese = get_blog_file_name(&Some("こんにちは".into())).unwrap();
+        assert!(!japanese.is_empty() && japanese != DEFAULT_BLOG_FILE_NAME);
+    }
+
+    #[test]
+    fn resolved_blog_file_name_takes_precedence() {
+        let data = std::fs::read_to_string("test/m2.md").unwrap();
+        let blog_file_info = BlogFile {
+            path_from_root: "doesntmatter".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "me".into(),
+            blog_file_name: Some(get_suffixed_blog_file_name("m2title", 2)),
+        };
+        let mut blog_config = BlogConfig::default();
+        let (_, _, outpath, _) = render_blog_actual(
+            &data, &blog_file_info, &Template::compile("").unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        assert_eq!(outpath, PathBuf::from("m2title-2"));
+    }
+
+    #[test]
+    fn local_assets_are_copied_and_links_rewritten() {
+        let asset_context = AssetContext {
+            blog_path_from_root: "posts/rust/BLOG.md",
+            blog_post_path: "my-post/",
+            blog_home_url: "https://example.com",
+            linkable_files: &["posts/rust/diagram.png", "posts/rust/img/b.png", "posts/shared/c.png"]
+                .iter().map(|f| f.to_string()).collect(),
+        };
+        let markdown = "![](./diagram.png) [a](img/b.png?x=1) ![](../shared/c.png) [web](https://example.com/d.png) [other post](../other/BLOG.md) [anchor](#top) \
+            [missing](./missing.png) [dir](../shared) [post](../other/post.txt)";
+        let rendered_markdown = render_markdown(markdown, &MarkdownConfig::default(), &asset_context);
+        let (html_out, assets) = (rendered_markdown.html, rendered_markdown.assets);
+        assert!(html_out.contains("src=\"https://example.com/my-post/diagram.png\""));
+        assert!(html_out.contains("href=\"https://example.com/my-post/img/b.png\""));
+        assert!(html_out.contains("src=\"https://example.com/my-post/posts/shared/c.png\""));
+        assert!(html_out.contains("href=\"https://example.com/d.png\""));
+        assert!(html_out.contains("href=\"../other/BLOG.md\""));
+        assert!(html_out.contains("href=\"#top\""));
+        assert!(html_out.contains("href=\"./missing.png\""));
+        assert!(html_out.contains("href=\"../shared\""));
+        assert!(html_out.contains("href=\"../other/post.txt\""));
+        assert_eq!(assets, vec![
+            BlogAsset { path_from_root: "posts/rust/diagram.png".into(), output_path: PathBuf::from("my-post/diagram.png") },
+            BlogAsset { path_from_root: "posts/rust/img/b.png".into(), output_path: PathBuf::from("my-post/img/b.png") },
+            BlogAsset { path_from_root: "posts/shared/c.png".into(), output_path: PathBuf::from("my-post/posts/shared/c.png") },
+        ]);
+
+        // cant go outside of the repository
+        assert_eq!(get_local_asset_path("BLOG.md", "../secret.png"), None);
+        assert_eq!(get_asset_url_path("my-post.html", "a.png"), "my-post_files/a.png");
+    }
+
+    fn render_with_markdown_header(header: &str, body: &str) -> String {
+        let data = format!("---\ntitle: t\n{}---\n\n{}", header, body);
+        let blog_file_info = BlogFile {
+            path_from_root: "BLOG.md".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "me".into(),
+            blog_file_name: None,
+        };
+        let mut blog_config = BlogConfig::default();
+        let (rendered, _, _, _) = render_blog_actual(
+            &data, &blog_file_info, &Template::compile("${{ rendered_markdown }}").unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        rendered
+    }
+
+    #[test]
+    fn markdown_extensions_are_off_by_default_and_can_be_enabled() {
+        let cases = [
+            ("tables", "| a | b |\n|---|---|\n| 1 | 2 |\n", "<td>1</td>"),
+            ("footnotes", "text[^1]\n\n[^1]: the note\n", "class=\"footnote-definition\""),
+            ("strikethrough", "~~gone~~\n", "<del>gone</del>"),
+            ("tasklists", "- [x] done\n- [ ] todo\n", "type=\"checkbox\" checked"),
+            ("smart_punctuation", "\"quoted\" -- dash\n", "“quoted” – dash"),
+        ];
+        for (option, input, expected) in cases {
+            let rendered = render_with_markdown_header("", input);
+            assert!(!rendered.contains(expected), "{} should be off by default: {}", option, rendered);
+            let rendered = render_with_markdown_header(&format!("markdown.{}: true\n", option), input);
+            assert!(rendered.contains(expected), "{} should be on: {}", option, rendered);
+        }
+    }
+
+    #[test]
+    fn markdown_header_overrides_blog_config() {
+        let mut blog_config = BlogConfig::default();
+        blog_config.markdown = Some(MarkdownConfig { tables: Some(true), footnotes: Some(true), ..Default::default() });
+        let (header_config, _) = parse_blog_file_info("---\nmarkdown.tables: false\n---\n").unwrap();
+        blog_config.apply(header_config);
+        let markdown_config = blog_config.markdown.unwrap();
+        assert_eq!(markdown_config.tables, Some(false));
+        assert_eq!(markdown_config.footnotes, Some(true));
+    }
+
+    #[test]
+    fn invalid_markdown_header_options_are_ignored() {
+        let (header_config, _) = parse_blog_file_info("---\nmarkdown.tabels: true\nmarkdown.tables: maybe\n---\n").unwrap();
+        assert_eq!(header_config.markdown.unwrap_or_default(), MarkdownConfig::default());
+    }
+
+    #[test]
+    fn fenced_code_blocks_are_highlighted() {
+        let rendered = render_with_markdown_header("", "```rust\nfn main() {}\n```\n");
+        assert!(rendered.contains("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(rendered.contains("<span class=\"hl-"));
+        // unknown languages are left as is
+        let rendered = render_with_markdown_header("", "```notalanguage\n<b>\n```\n");
+        assert!(rendered.contains("<pre><code class=\"language-notalanguage\">&lt;b&gt;\n</code></pre>"));
+        let rendered = render_with_markdown_header("markdown.syntax_highlighting: false\n", "```rust\nfn main() {}\n```\n");
+        assert!(!rendered.contains("hl-"));
+        assert!(get_highlight_css(DEFAULT_HIGHLIGHT_THEME).unwrap().contains(".hl-code"));
+    }
+
+    #[test]
+    fn headings_get_unique_ids_and_a_toc() {
+        let asset_context = AssetContext {
+            blog_path_from_root: "BLOG.md",
+            blog_post_path: "my-post",
+            blog_home_url: "",
+            linkable_files: &HashSet::new(),
+        };
+        let mut markdown_config = MarkdownConfig::default();
+        markdown_config.heading_anchors = Some(true);
+        let markdown = "## Intro\n\n### `code` part\n\n## Intro\n\n# Привет\n";
+        let rendered_markdown = render_markdown(markdown, &markdown_config, &asset_context);
+        assert!(rendered_markdown.html.contains("<h2 id=\"intro\">Intro<a class=\"heading-anchor\" href=\"#intro\" aria-hidden=\"true\">#</a></h2>"));
+        assert!(rendered_markdown.html.contains("<h3 id=\"code-part\"><code>code</code> part"));
+        assert!(rendered_markdown.html.contains("<h2 id=\"intro-1\">"));
+        assert!(rendered_markdown.html.contains("<h1 id=\"privet\">"));
+
+        let toc_html = get_toc_html(&rendered_markdown.headings);
+        let expected = "<nav class=\"toc\">\n<ul>\n\
+            <li><a href=\"#intro\">Intro</a>\n<ul>\n\
+            <li><a href=\"#code-part\">code part</a></li>\n</ul>\n</li>\n\
+            <li><a href=\"#intro-1\">Intro</a></li>\n\
+            <li><a href=\"#privet\">Привет</a></li>\n</ul>\n</nav>\n";
+        assert_eq!(toc_html, expected);
+    }
+
+    #[test]
+    fn word_count_skips_code_blocks() {
+        let blog_text = "one two `three`\n\n```\nnot counted at all\n```\n\n- four\n- [five](https://example.com)\n";
+        assert_eq!(get_word_count(blog_text, &MarkdownConfig::default()), 5);
+        // without tables, the pipes are text
+        let table = "| a | b |\n|---|---|\n| 1 | 2 |\n";
+        assert_eq!(get_word_count(table, &MarkdownConfig::default()), 11);
+        assert_eq!(get_word_count(table, &MarkdownConfig { tables: Some(true), ..Default::default() }), 4);
+        assert_eq!(get_reading_time_minutes(0), 0);
+        assert_eq!(get_reading_time_minutes(1), 1);
+        assert_eq!(get_reading_time_minutes(WORDS_PER_MINUTE + 1), 2);
+
+        let data = "---\ntitle: t\n---\n\none two three\n";
+        let blog_file_info = BlogFile {
+            path_from_root: "BLOG.md".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "me".into(),
+            blog_file_name: None,
+        };
+        let (blog_info, _) = get_applied_blog_config(data, &blog_file_info, &BlogConfig::default()).unwrap();
+        let (link, _) = render_blogpost_link(
+            &blog_info, &Template::compile("${{ word_count }} words, ${{ reading_time_minutes }} min").unwrap());
+        assert_eq!(link, "3 words, 1 min");
+
+        let mut blog_info = blog_info;
+        blog_info.blog_home_url = Some("https://example.com".into());
+        blog_info.blog_name = Some("my blog".into());
+        blog_info.blog_description = Some("d".into());
+        let rss_item = generate_rss_item(&blog_info, &blog_file_info).unwrap();
+        assert!(rss_item.contains("<blog:wordCount>3</blog:wordCount>"));
+        assert!(rss_item.contains("<blog:readingTimeMinutes>1</blog:readingTimeMinutes>"));
+        let rss = generate_rss(&blog_info, &blog_file_info, &rss_item).unwrap();
+        assert!(rss.contains(&format!("xmlns:blog=\"{}\"", RSS_EXTENSION_NAMESPACE)));
+        assert!(rss.contains("<blog:wordCount>3</blog:wordCount>"));
+    }
+
+    #[test]
+    fn layout_keys_dont_leak_placeholders() {
+        let data = "---\ntitle: my title\n---\n\nthe body\n";
+        let blog_file_info = BlogFile {
+            path_from_root: "BLOG.md".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "".into(),
+            blog_file_name: None,
+        };
+        let template = "${{ rendered_markdown }}|${{ rendered_body }}|${{ byline_html }}|${{ about_me_html }}";
+        let mut blog_config = BlogConfig {
+            date_written: Some("today".into()),
+            ..Default::default()
+        };
+        let (rendered, _, _, _) = render_blog_actual(data, &blog_file_info, &Template::compile(template).unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        let parts: Vec<&str> = rendered.split('|').collect();
+        assert_eq!(parts[0], "<p>the body</p>\n");
+        assert_eq!(parts[1], "<p>the body</p>\n");
+        assert!(parts[2].contains("today"));
+        assert!(!rendered.contains("AUTHORNAMENOTFOUND"));
+        assert_eq!(parts[3], "");
+
+        blog_config.legacy_layout = Some(true);
+        blog_config.author_email = Some("me@example.com".into());
+        let (rendered, _, _, _) = render_blog_actual(data, &blog_file_info, &Template::compile(template).unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        let parts: Vec<&str> = rendered.split('|').collect();
+        assert!(parts[0].contains("my title</h1>"));
+        assert!(parts[0].contains("today"));
+        assert!(parts[0].contains("me@example.com"));
+        assert_eq!(parts[1], "<p>the body</p>\n");
+        assert!(parts[3].contains("me@example.com"));
+    }
+
+    #[test]
+    fn custom_templates_without_the_new_keys_keep_the_legacy_layout() {
+        let custom = Some(PathBuf::from("custom.html"));
+        let old_template = Template::compile("<body>${{ rendered_markdown }}</body>").unwrap();
+        let mut blog_config = BlogConfig::default();
+        set_default_layout(&mut blog_config, &old_template, &custom);
+        assert_eq!(blog_config.legacy_layout, Some(true));
+
+        let mut blog_config = BlogConfig::default();
+        set_default_layout(&mut blog_config, &old_template, &None);
+        assert_eq!(blog_config.legacy_layout, None);
+
+        let mut blog_config = BlogConfig { legacy_layout: Some(false), ..Default::default() };
+        set_default_layout(&mut blog_config, &old_template, &custom);
+        assert_eq!(blog_config.legacy_layout, Some(false));
+
+        let new_template = Template::compile("${% if title %}${% for tag in tags %}${{ byline_html }}${% endfor %}${% endif %}").unwrap();
+        let mut blog_config = BlogConfig::default();
+        set_default_layout(&mut blog_config, &new_template, &custom);
+        assert_eq!(blog_config.legacy_layout, None);
+
+        let default_template = Template::compile(&get_template(&None).unwrap()).unwrap();
+        assert!(template_uses_any_key(&default_template.nodes, LAYOUT_TEMPLATE_KEYS));
+    }
+
+    #[test]
+    fn homepage_can_loop_over_posts_and_links() {
+        let blog_config = BlogConfig {
+            blog_home_url: Some("https://example.com".into()),
+            ..Default::default()
+        };
+        let first = BlogConfig {
+            title: Some("first".into()),
+            tags: Some(vec!["a".into(), "b".into()]),
+            ..Default::default()
+        };
+        let second = BlogConfig {
+            title: Some("second".into()),
+            ..Default::default()
+        };
+        let template = "${% for post in posts %}${{ post.title }}(${% for tag in post.tags %}${{ tag }}${% endfor %}) ${% endfor %}\
+            ${% for link in links %}<a href=\"${{ link.url }}\">${{ link.name }}</a>${% endfor %}";
+        let (rendered, diagnostics) = render_blog_homepage(
+            &blog_config, &[first.clone(), second.clone()], "", template, None).unwrap();
+        assert_eq!(rendered, "first(ab) second() <a href=\"https://example.com/rss.xml\">RSS</a>");
+        assert!(diagnostics.is_empty());
+
+        let (_, diagnostics) = render_blog_homepage(
+            &blog_config, &[first, second], "", "${% for post in posts %}\n  ${{ post.nope }}${% endfor %}", None).unwrap();
+        let warnings: Vec<String> = diagnostics.iter()
+            .map(|d| d.to_string_with_template_name("index.html")).collect();
+        assert_eq!(warnings, vec![
+            "index.html:2:3: unknown key 'post.nope'",
+            "index.html:2:3: unknown key 'post.nope'",
+        ]);
+    }
+
+    #[test]
+    fn templates_can_use_env_variables_and_commands() {
+        std::env::set_var("BLOG_UPDATER_TEST_VAR", "<from env>");
+        let template = "E{{ BLOG_UPDATER_TEST_VAR }} !{{ echo 1.2.3 }} ${{ blog_name }}";
+        let mut blog_config = BlogConfig {
+            blog_name: Some("my blog".into()),
+            ..Default::default()
+        };
+        // commands are only run if the blog config allows it
+        let (rendered, diagnostics) = render_blog_homepage(&blog_config, &[], "", template, None).unwrap();
+        assert_eq!(rendered, "&lt;from env&gt;  my blog");
+        assert_eq!(diagnostics[0].to_string_with_template_name("index.html"), "index.html:1:30: unknown key 'echo 1.2.3'");
+
+        blog_config.template_commands = Some(true);
+        let (rendered, diagnostics) = render_blog_homepage(&blog_config, &[], "", template, None).unwrap();
+        assert_eq!(rendered, "&lt;from env&gt; 1.2.3 my blog");
+        assert!(diagnostics.is_empty());
+
+        // commands run in the root of the repo, not where we are
+        blog_config.git_root = Some(PathBuf::from("/"));
+        let (rendered, _) = render_blog_homepage(&blog_config, &[], "", "!{{ pwd }}", None).unwrap();
+        assert_eq!(rendered, "/");
+    }
+
+    #[test]
+    fn blog_templates_can_escape_placeholders() {
+        let blog_config = BlogConfig { blog_name: Some("my blog".into()), ..Default::default() };
+        let template = "\\${{ blog_name }} ${{ blog_name }} \\\\${{ blog_name }}";
+        let (rendered, _) = render_blog_homepage(&blog_config, &[], "", template, None).unwrap();
+        assert_eq!(rendered, "${{ blog_name }} my blog \\my blog");
+        let compiled = compile_template("\\${% if blog_name %}", None).unwrap();
+        assert_eq!(compiled.render(&HashMap::<&str, &str>::new(), FailureMode::FM_ignore, &RenderOptions::default()), "${% if blog_name %}");
+    }
+
+    #[test]
+    fn git_lines_are_filtered_and_failures_have_stderr() {
+        let lines = get_git_lines(&["sh", "-c", "echo a/BLOG.md; echo b.png; echo c/BLOG.md"],
+            || "unused".into(), |line| if line.ends_with("BLOG.md") { Some(line) } else { None }).unwrap();
+        assert_eq!(lines, vec!["a/BLOG.md", "c/BLOG.md"]);
+
+        let err = get_git_lines(&["sh", "-c", "echo a; echo bad ref >&2; exit 128"],
+            || "Failed to list files".into(), Some).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to list files: bad ref");
+    }
+
+    #[test]
+    fn strict_mode_fails_on_missing_keys() {
+        let blog_config = BlogConfig::default();
+        let (_, diagnostics) = render_blog_homepage(
+            &blog_config, &[], "", "${{ title }}\n${{ nope }} ${{ nope }}", None).unwrap();
+        assert!(check_template_diagnostics(&diagnostics, "index.html", "the homepage", false).is_ok());
+        let err = check_template_diagnostics(&diagnostics, "index.html", "the homepage", true).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to find a value for: title, nope\n  \
+            index.html:1:1: unknown key 'title'\n  \
+            index.html:2:1: unknown key 'nope'\n  \
+            index.html:2:13: unknown key 'nope' (while rendering the homepage)");
+    }
+
+    #[test]
+    fn template_values_are_escaped_unless_they_are_html() {
+        let data = "---\ntitle: \"quotes\" & <tags>\n---\n\n<b>body</b>\n";
+        let blog_file_info = BlogFile {
+            path_from_root: "BLOG.md".into(),
+            updated: 1621897682,
+            written: 1621897682,
+            git_author_name: "me".into(),
+            blog_file_name: None,
+        };
+        let template = "<title>${{ title }}</title><meta content=\"${{ description }}\">${{ rendered_body }}";
+        let mut blog_config = BlogConfig {
+            description: Some("a \"description\"".into()),
+            ..Default::default()
+        };
+        let (rendered, _, _, _) = render_blog_actual(
+            data, &blog_file_info, &Template::compile(template).unwrap(), &HashSet::new(), &mut blog_config).unwrap();
+        assert_eq!(rendered, "<title>\"quotes\" &amp; &lt;tags&gt;</title><meta content=\"a &quot;description&quot;\"><p><b>body</b></p>\n");
+    }
 }
@@ -0,0 +1,388 @@
+use pulldown_cmark::{Parser, Options, Event, Tag, CodeBlockKind, html};
+use pulldown_cmark::escape::escape_html;
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use super::urls::*;
+use super::highlight::*;
+use super::slugify;
+
+/// which markdown extensions to enable. these are all off by
+/// default, which is plain commonmark. this can be set in the `markdown` section
+/// of the blog config, and overridden per blog file in the header, eg:
+/// `markdown.tables: true`.
+/// syntax_highlighting is on by default, and highlights fenced code blocks
+/// that have a language, eg: ```rust.
+/// heading_anchors adds a `#` link to every heading that links to itself
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct MarkdownConfig {
+    pub tables: Option<bool>,
+    pub footnotes: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub tasklists: Option<bool>,
+    pub smart_punctuation: Option<bool>,
+    pub syntax_highlighting: Option<bool>,
+    pub heading_anchors: Option<bool>,
+}
+
+impl MarkdownConfig {
+    pub fn apply(&mut self, other: MarkdownConfig) {
+        if let Some(b) = other.tables {
+            self.tables = Some(b);
+        }
+        if let Some(b) = other.footnotes {
+            self.footnotes = Some(b);
+        }
+        if let Some(b) = other.strikethrough {
+            self.strikethrough = Some(b);
+        }
+        if let Some(b) = other.tasklists {
+            self.tasklists = Some(b);
+        }
+        if let Some(b) = other.smart_punctuation {
+            self.smart_punctuation = Some(b);
+        }
+        if let Some(b) = other.syntax_highlighting {
+            self.syntax_highlighting = Some(b);
+        }
+        if let Some(b) = other.heading_anchors {
+            self.heading_anchors = Some(b);
+        }
+    }
+
+    /// sets an option by its name, as it would appear after
+    /// `markdown.` in the blog header. returns false if its not a valid option
+    pub fn set_option(&mut self, name: &str, enabled: bool) -> bool {
+        match name {
+            "tables" => self.tables = Some(enabled),
+            "footnotes" => self.footnotes = Some(enabled),
+            "strikethrough" => self.strikethrough = Some(enabled),
+            "tasklists" => self.tasklists = Some(enabled),
+            "smart_punctuation" => self.smart_punctuation = Some(enabled),
+            "syntax_highlighting" => self.syntax_highlighting = Some(enabled),
+            "heading_anchors" => self.heading_anchors = Some(enabled),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn get_options(&self) -> Options {
+        let mut options = Options::empty();
+        if self.tables.unwrap_or(false) {
+            options.insert(Options::ENABLE_TABLES);
+        }
+        if self.footnotes.unwrap_or(false) {
+            options.insert(Options::ENABLE_FOOTNOTES);
+        }
+        if self.strikethrough.unwrap_or(false) {
+            options.insert(Options::ENABLE_STRIKETHROUGH);
+        }
+        if self.tasklists.unwrap_or(false) {
+            options.insert(Options::ENABLE_TASKLISTS);
+        }
+        if self.smart_punctuation.unwrap_or(false) {
+            options.insert(Options::ENABLE_SMART_PUNCTUATION);
+        }
+        options
+    }
+}
+
+/// a file that is referenced relatively from a blog file, like
+/// an image: `![](./diagram.png)`. it needs to be read from git, and
+/// written to the output directory next to the blog post
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlogAsset {
+    pub path_from_root: String,
+    pub output_path: PathBuf,
+}
+
+/// information about where the blog post is coming from, and where
+/// it is going to, so that relative links can be rewritten
+pub struct AssetContext<'a> {
+    pub blog_path_from_root: &'a str,
+    pub blog_post_path: &'a str,
+    pub blog_home_url: &'a str,
+    /// the files in the repository that can be copied, ie: every file
+    /// except the blog files. links to anything else are left as they are
+    pub linkable_files: &'a HashSet<String>,
+}
+
+/// returns true if this link points somewhere else entirely, ie: it has
+/// a scheme like https: or mailto:, or its an anchor, or its absolute
+pub fn is_external_link(dest: &str) -> bool {
+    if dest.is_empty() || dest.starts_with('/') || dest.starts_with('#') || dest.starts_with('?') {
+        return true;
+    }
+    let before_slash = match dest.find('/') {
+        Some(i) => &dest[0..i],
+        None => dest,
+    };
+    before_slash.contains(':')
+}
+
+/// resolves a relative link from a blog file to the path of the file
+/// in the repository. returns the path from the root of the repository,
+/// and the path that it should have relative to the blog post's asset directory.
+/// returns None if this isnt a link to a local file, or if it tries to
+/// go outside of the repository.
+pub fn get_local_asset_path(blog_path_from_root: &str, dest: &str) -> Option<(String, String)> {
+    if is_external_link(dest) {
+        return None;
+    }
+    // we dont care about the query or fragment, only the file
+    let dest = match dest.find(['?', '#']) {
+        Some(i) => &dest[0..i],
+        None => dest,
+    };
+    // other markdown files are probably other blog posts, not assets
+    if dest.is_empty() || dest.ends_with('/') || dest.ends_with(".md") {
+        return None;
+    }
+
+    let mut components: Vec<&str> = blog_path_from_root.split('/').collect();
+    // the last component is the blog file itself
+    components.pop();
+    let blog_dir_len = components.len();
+    let mut min_len = blog_dir_len;
+    for component in dest.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => {
+                components.pop()?;
+                min_len = min_len.min(components.len());
+            }
+            c => components.push(c),
+        }
+    }
+
+    let path_from_root = components.join("/");
+    // if the asset is within the blog's directory, we keep that structure,
+    // otherwise we use its path from the root of the repository
+    let relative_path = if min_len == blog_dir_len {
+        components[blog_dir_len..].join("/")
+    } else {
+        path_from_root.clone()
+    };
+    Some((path_from_root, relative_path))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: u32,
+    pub id: String,
+    pub text: String,
+}
+
+pub struct RenderedMarkdown {
+    pub html: String,
+    /// the local files that were linked to
+    pub assets: Vec<BlogAsset>,
+    /// every heading in the order they appear, used to make a table of contents
+    pub headings: Vec<Heading>,
+}
+
+/// the id of a heading is made from its text. if another heading already
+/// has that id, we add -1, -2, etc. until its unique
+pub fn get_heading_id(text: &str, used_ids: &mut HashSet<String>) -> String {
+    let mut base_id = slugify(text);
+    if base_id.is_empty() {
+        base_id = "section".into();
+    }
+    let mut id = base_id.clone();
+    let mut suffix = 0;
+    while used_ids.contains(&id) {
+        suffix += 1;
+        id = format!("{}-{}", base_id, suffix);
+    }
+    used_ids.insert(id.clone());
+    id
+}
+
+/// fenced code blocks are replaced with the html of their highlighted code.
+/// the code block is the text events in between the start and end of the block
+pub fn highlight_code_blocks<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut out_events = vec![];
+    let mut code_block: Option<(String, String)> = None;
+    for event in events {
+        match (event, &mut code_block) {
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info_string))), None) => {
+                code_block = Some((info_string.to_string(), String::from("")));
+            }
+            (Event::Text(text), Some((_, code))) => code.push_str(&text),
+            (Event::End(Tag::CodeBlock(_)), Some((info_string, code))) => {
+                out_events.push(Event::Html(highlight_code_block(info_string, code).into()));
+                code_block = None;
+            }
+            (event, _) => out_events.push(event),
+        }
+    }
+    out_events
+}
+
+/// gives every heading an id so that it can be linked to. we have to write
+/// the start and end of the heading ourselves because pulldown_cmark
+/// doesnt support adding attributes to the tags it writes
+pub fn add_heading_ids<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    heading_anchors: bool,
+) -> (Vec<Event<'a>>, Vec<Heading>) {
+    let mut out_events = vec![];
+    let mut headings = vec![];
+    let mut used_ids = HashSet::new();
+    // the level, the text, and the events inside of the heading we are currently in
+    let mut current_heading: Option<(u32, String, Vec<Event<'a>>)> = None;
+    for event in events {
+        match (event, &mut current_heading) {
+            (Event::Start(Tag::Heading(level)), None) => {
+                current_heading = Some((level, String::from(""), vec![]));
+            }
+            (Event::End(Tag::Heading(_)), Some((level, text, inner_events))) => {
+                let level = *level;
+                let id = get_heading_id(text, &mut used_ids);
+                out_events.push(Event::Html(format!("<h{} id=\"{}\">", level, id).into()));
+                out_events.append(inner_events);
+                if heading_anchors {
+                    let anchor = format!("<a class=\"heading-anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>", id);
+                    out_events.push(Event::Html(anchor.into()));
+                }
+                out_events.push(Event::Html(format!("</h{}>\n", level).into()));
+                headings.push(Heading { level, id, text: text.clone() });
+                current_heading = None;
+            }
+            (event, Some((_, text, inner_events))) => {
+                if let Event::Text(s) | Event::Code(s) = &event {
+                    text.push_str(s);
+                }
+                inner_events.push(event);
+            }
+            (event, None) => out_events.push(event),
+        }
+    }
+    (out_events, headings)
+}
+
+/// a nested list of links to every heading. a heading that is a
+/// lower level than the one before it goes in a nested list
+pub fn get_toc_html(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return "".into();
+    }
+    let mut html_out = String::from("<nav class=\"toc\">\n");
+    // the levels of the lists that are currently open
+    let mut levels: Vec<u32> = vec![];
+    for heading in headings {
+        match levels.last() {
+            Some(&last_level) if heading.level <= last_level => {
+                html_out.push_str("</li>\n");
+                while levels.len() > 1 && heading.level <= levels[levels.len() - 2] {
+                    html_out.push_str("</ul>\n</li>\n");
+                    levels.pop();
+                }
+                if let Some(last_level) = levels.last_mut() {
+                    *last_level = heading.level;
+                }
+            }
+            _ => {
+                if !levels.is_empty() {
+                    html_out.push('\n');
+                }
+                html_out.push_str("<ul>\n");
+                levels.push(heading.level);
+            }
+        }
+        html_out.push_str(&format!("<li><a href=\"#{}\">", heading.id));
+        let _ = escape_html(&mut html_out, &heading.text);
+        html_out.push_str("</a>");
+    }
+    html_out.push_str("</li>\n");
+    while levels.len() > 1 {
+        html_out.push_str("</ul>\n</li>\n");
+        levels.pop();
+    }
+    html_out.push_str("</ul>\n</nav>\n");
+    html_out
+}
+
+/// renders the markdown text to html. any local files that are
+/// linked to are returned so that they can be copied to the output,
+/// and the links are rewritten to point to where they will be copied to.
+/// only links to the linkable_files of the asset context are rewritten
+pub fn render_markdown(
+    markdown: &str,
+    markdown_config: &MarkdownConfig,
+    asset_context: &AssetContext,
+) -> RenderedMarkdown {
+    let mut assets: Vec<BlogAsset> = vec![];
+    let mut rewrite_dest = |dest: &str| -> Option<String> {
+        let (path_from_root, relative_path) = get_local_asset_path(asset_context.blog_path_from_root, dest)?;
+        if !asset_context.linkable_files.contains(&path_from_root) {
+            return None;
+        }
+        let asset_url_path = get_asset_url_path(asset_context.blog_post_path, &relative_path);
+        let asset = BlogAsset {
+            path_from_root,
+            output_path: get_output_path_from_url_path(&asset_url_path),
+        };
+        if !assets.contains(&asset) {
+            assets.push(asset);
+        }
+        Some(format!("{}/{}", asset_context.blog_home_url, asset_url_path))
+    };
+
+    let syntax_highlighting = markdown_config.syntax_highlighting.unwrap_or(true);
+    let parser = Parser::new_ext(markdown, markdown_config.get_options()).map(|event| match event {
+        Event::Start(Tag::Image(link_type, dest, title)) => {
+            let dest = rewrite_dest(&dest).map_or(dest, |d| d.into());
+            Event::Start(Tag::Image(link_type, dest, title))
+        }
+        Event::Start(Tag::Link(link_type, dest, title)) => {
+            let dest = rewrite_dest(&dest).map_or(dest, |d| d.into());
+            Event::Start(Tag::Link(link_type, dest, title))
+        }
+        _ => event,
+    });
+
+    let events = if syntax_highlighting {
+        highlight_code_blocks(parser)
+    } else {
+        parser.collect()
+    };
+    let heading_anchors = markdown_config.heading_anchors.unwrap_or(false);
+    let (events, headings) = add_heading_ids(events.into_iter(), heading_anchors);
+
+    let mut html_out = String::from("");
+    html::push_html(&mut html_out, events.into_iter());
+    RenderedMarkdown {
+        html: html_out,
+        assets,
+        headings,
+    }
+}
+
+/// the average reading speed used to estimate the reading time
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// counts the words that would be read, ie: the text of the markdown
+/// but not code blocks, html, or the markdown syntax itself.
+/// blog_text should not include the blog header, and it is parsed
+/// with the same options that it is rendered with
+pub fn get_word_count(blog_text: &str, markdown_config: &MarkdownConfig) -> usize {
+    let mut word_count = 0;
+    let mut in_code_block = false;
+    for event in Parser::new_ext(blog_text, markdown_config.get_options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(text) | Event::Code(text) if !in_code_block => {
+                word_count += text.split_whitespace().count();
+            }
+            _ => {},
+        }
+    }
+    word_count
+}
+
+/// rounded up, so that a short blog post is a 1 minute read instead of 0
+pub fn get_reading_time_minutes(word_count: usize) -> usize {
+    word_count.div_ceil(WORDS_PER_MINUTE)
+}
@@ -2,6 +2,7 @@ use super::BlogFile;
 use super::BlogConfig;
 
 pub const RSS_ENDING: &str = "rss.xml";
+pub const RSS_EXTENSION_NAMESPACE: &str = "https://github.com/nikita-skobov/blog-updater";
 
 pub fn rss_err(blog_file: &BlogFile, missing: &str) -> String {
     let err_msg = format!("Blog post {} is missing {}", blog_file.path_from_root, missing);
@@ -20,9 +21,9 @@ pub fn generate_rss_item(
         Some(s) => s,
         None => return Err(rss_err(blog_file, "blog_home_url"))
     };
-    let blog_file_name = match &blog_config.blog_file_name {
+    let blog_post_path = match &blog_config.blog_post_path {
         Some(s) => s,
-        None => return Err(rss_err(blog_file, "blog_file_name (this is supposed to be generated for you, but somehow we failed to parse the blog file name?)"))
+        None => return Err(rss_err(blog_file, "blog_post_path (this is supposed to be generated for you, but somehow we failed to parse the blog file name?)"))
     };
     let description = match &blog_config.description {
         Some(s) => s,
@@ -39,7 +40,16 @@ pub fn generate_rss_item(
     let datetime: chrono::DateTime<chrono::Utc> = chrono::DateTime::from_utc(naive, chrono::Utc);
     let human_date = datetime.format("%a, %d %B %Y %H:%M:%S %z").to_string();
 
-    let link = format!("{}/{}", blog_home_url, blog_file_name);
+    let link = format!("{}/{}", blog_home_url, blog_post_path);
+
+    // these are not part of the RSS spec, so they are in the blog-updater namespace
+    let mut extension_xml: String = "".into();
+    if let Some(n) = blog_config.word_count {
+        extension_xml = format!("{}\n    <blog:wordCount>{}</blog:wordCount>", extension_xml, n);
+    }
+    if let Some(n) = blog_config.reading_time_minutes {
+        extension_xml = format!("{}\n    <blog:readingTimeMinutes>{}</blog:readingTimeMinutes>", extension_xml, n);
+    }
 
     let rss_item = format!("
     <item>
@@ -47,13 +57,14 @@ pub fn generate_rss_item(
     <link>{}</link>
     <pubDate>{}</pubDate>
     <guid>{}</guid>
-    <description>{}</description>
+    <description>{}</description>{}
     </item>",
     title,
     link,
     human_date,
     link,
     description,
+    extension_xml,
     );
 
     Ok(rss_item)
@@ -86,7 +97,7 @@ pub fn generate_rss(
     let rss_location = format!("{}/{}", blog_home_url, RSS_ENDING);
 
     let rss_xml = format!("
-    <rss xmlns:atom=\"http://www.w3.org/2005/Atom\" version=\"2.0\">
+    <rss xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:blog=\"{}\" version=\"2.0\">
     <channel>
     <title>{}</title>
     <link>{}</link>
@@ -97,6 +108,7 @@ pub fn generate_rss(
     {}
     </channel>
     </rss>",
+    RSS_EXTENSION_NAMESPACE,
     title,
     blog_home_url,
     description,
@@ -0,0 +1,101 @@
+use super::BlogFile;
+use super::BlogConfig;
+use super::get_date_string_from_timestamp;
+
+pub const SITEMAP_ENDING: &str = "sitemap.xml";
+pub const ROBOTS_ENDING: &str = "robots.txt";
+
+pub fn sitemap_err(blog_file: &BlogFile, missing: &str) -> String {
+    let err_msg = format!("Blog post {} is missing {}", blog_file.path_from_root, missing);
+    err_msg
+}
+
+/// urls can contain characters like '&' that
+/// are not valid inside of an xml element
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// the modified_time_iso is only generated if the blog header
+/// did not contain a date, so if its missing we fall back
+/// to the timestamp of the most recent commit of this blog file
+pub fn get_lastmod(blog_config: &BlogConfig, blog_file: &BlogFile) -> Option<String> {
+    if let Some(s) = &blog_config.modified_time_iso {
+        return Some(s.clone());
+    }
+    let timestamp = if blog_file.updated != 0 { blog_file.updated } else { blog_file.written };
+    if timestamp == 0 {
+        return None;
+    }
+    let (_, iso) = get_date_string_from_timestamp(timestamp);
+    Some(iso)
+}
+
+pub fn generate_sitemap_url(loc: &str, lastmod: &Option<String>) -> String {
+    let lastmod_xml = match lastmod {
+        Some(s) => format!("\n    <lastmod>{}</lastmod>", s),
+        None => "".into(),
+    };
+    format!("
+    <url>
+    <loc>{}</loc>{}
+    </url>",
+    escape_xml(loc),
+    lastmod_xml,
+    )
+}
+
+pub fn generate_sitemap_item(
+    blog_config: &BlogConfig,
+    blog_file: &BlogFile,
+) -> Result<String, String> {
+    let blog_home_url = match &blog_config.blog_home_url {
+        Some(s) => s,
+        None => return Err(sitemap_err(blog_file, "blog_home_url"))
+    };
+    let blog_post_path = match &blog_config.blog_post_path {
+        Some(s) => s,
+        None => return Err(sitemap_err(blog_file, "blog_post_path (this is supposed to be generated for you, but somehow we failed to parse the blog file name?)"))
+    };
+
+    let link = format!("{}/{}", blog_home_url, blog_post_path);
+    Ok(generate_sitemap_url(&link, &get_lastmod(blog_config, blog_file)))
+}
+
+/// sitemap_items_xml is every blog post's <url> element. the homepage
+/// is added here, and its lastmod is the most recent lastmod of all of the posts
+pub fn generate_sitemap(
+    blog_config: &BlogConfig,
+    homepage_lastmod: &Option<String>,
+    sitemap_items_xml: &str,
+) -> Result<String, String> {
+    let blog_home_url = match &blog_config.blog_home_url {
+        Some(s) => s,
+        None => return Err("blog config is missing blog_home_url".into())
+    };
+    let homepage_link = format!("{}/", blog_home_url);
+    let homepage_xml = generate_sitemap_url(&homepage_link, homepage_lastmod);
+
+    let sitemap_xml = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+    <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">{}
+    {}
+    </urlset>",
+    homepage_xml,
+    sitemap_items_xml,
+    );
+
+    Ok(sitemap_xml)
+}
+
+pub fn generate_robots_txt(blog_config: &BlogConfig) -> Result<String, String> {
+    let blog_home_url = match &blog_config.blog_home_url {
+        Some(s) => s,
+        None => return Err("blog config is missing blog_home_url".into())
+    };
+    let robots_txt = format!("User-agent: *\nAllow: /\n\nSitemap: {}/{}\n", blog_home_url, SITEMAP_ENDING);
+    Ok(robots_txt)
+}
@@ -0,0 +1,127 @@
+use serde::{Serialize, Deserialize};
+use std::path::PathBuf;
+
+/// how the rendered blog posts are laid out in the output directory,
+/// and therefore what their URLs look like:
+/// - bare: `my-post` (an extensionless file. your host needs to serve these as html)
+/// - html: `my-post.html`
+/// - directory: `my-post/index.html`, which is linked as `my-post/`
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlStyle {
+    #[default]
+    Bare,
+    Html,
+    Directory,
+}
+
+impl UrlStyle {
+    /// the path that goes after the blog_home_url
+    /// in order to link to this blog post
+    pub fn get_url_path(&self, blog_file_name: &str) -> String {
+        match self {
+            UrlStyle::Bare => blog_file_name.to_owned(),
+            UrlStyle::Html => format!("{}.html", blog_file_name),
+            UrlStyle::Directory => format!("{}/", blog_file_name),
+        }
+    }
+}
+
+/// what to do if two blog files would be written to the same url:
+/// - error: stop, and tell the user which blog files collided
+/// - suffix: add -2, -3, etc. to the blog_file_name of the newer blog files
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugCollision {
+    Error,
+    #[default]
+    Suffix,
+}
+
+pub fn get_suffixed_blog_file_name(blog_file_name: &str, suffix: usize) -> String {
+    format!("{}-{}", blog_file_name, suffix)
+}
+
+/// the default permalink is just the blog file name at the root of the blog
+pub const DEFAULT_PERMALINK: &str = "{slug}";
+
+/// expands a permalink pattern like `/{year}/{month}/{slug}` or `/{dir}/{slug}`
+/// into the path of this blog post (without the url style applied).
+/// the available placeholders are:
+/// - {slug}: the blog_file_name, either from the blog header or generated from the title
+/// - {dir}: the directory of the blog file within the repository
+/// - {year}, {month}, {day}: of when the blog file was first committed
+pub fn expand_permalink(
+    pattern: &str,
+    slug: &str,
+    path_from_root: &str,
+    written: i64,
+) -> Result<String, String> {
+    let naive = chrono::NaiveDateTime::from_timestamp(written, 0);
+    let datetime: chrono::DateTime<chrono::Utc> = chrono::DateTime::from_utc(naive, chrono::Utc);
+    let dir = match path_from_root.rfind('/') {
+        Some(i) => &path_from_root[0..i],
+        None => "",
+    };
+
+    let mut expanded: String = "".into();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[0..start]);
+        let end = rest[start..].find('}')
+            .map_or(Err(format!("Unclosed '{{' in permalink pattern '{}'", pattern)), |i| Ok(start + i))?;
+        let value = match &rest[(start + 1)..end] {
+            "slug" => slug.to_owned(),
+            "dir" => dir.to_owned(),
+            "year" => datetime.format("%Y").to_string(),
+            "month" => datetime.format("%m").to_string(),
+            "day" => datetime.format("%d").to_string(),
+            other => return Err(format!("Unknown placeholder '{{{}}}' in permalink pattern '{}'", other, pattern)),
+        };
+        expanded.push_str(&value);
+        rest = &rest[(end + 1)..];
+    }
+    expanded.push_str(rest);
+
+    // a placeholder like {dir} can be empty, so we
+    // remove any empty components along with the leading slash
+    let components: Vec<&str> = expanded.split('/').filter(|c| !c.is_empty()).collect();
+    if components.is_empty() {
+        return Err(format!("Permalink pattern '{}' resolved to an empty path", pattern));
+    }
+    Ok(components.join("/"))
+}
+
+/// the path relative to the output directory that a blog post
+/// with this url path should be written to. a url path that ends
+/// with a slash is a directory, so the blog post is its index.html
+pub fn get_output_path_from_url_path(url_path: &str) -> PathBuf {
+    let mut outpath = PathBuf::new();
+    for component in url_path.split('/').filter(|c| !c.is_empty()) {
+        outpath.push(component);
+    }
+    if url_path.ends_with('/') {
+        outpath.push("index.html");
+    }
+    outpath
+}
+
+/// the relative path from a blog post with this url path back to the output
+/// directory, eg: `2024/05/slug/` is written to `2024/05/slug/index.html`, so its `../../..`.
+/// templates use it for links when there is no blog_home_url
+pub fn get_root_path_from_url_path(url_path: &str) -> String {
+    let depth = get_output_path_from_url_path(url_path).components().count().saturating_sub(1);
+    if depth == 0 { ".".into() } else { vec![".."; depth].join("/") }
+}
+
+/// files that a blog post links to are copied next to it. if the blog post
+/// is in its own directory, they go in that directory, otherwise
+/// they go in a `<blog post>_files/` directory
+pub fn get_asset_url_path(blog_post_path: &str, relative_path: &str) -> String {
+    if blog_post_path.ends_with('/') {
+        format!("{}{}", blog_post_path, relative_path)
+    } else {
+        let stem = blog_post_path.strip_suffix(".html").unwrap_or(blog_post_path);
+        format!("{}_files/{}", stem, relative_path)
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use context_based_variable_substitution::{
+    render_template, replace_all_from, FailureMode, RenderOptions, Template,
+};
+
+/// about as many posts as a big blog would have
+const NUM_POSTS: usize = 3000;
+
+const POST_LINK_TEMPLATE: &str = "<div class=\"bloglink\"><a class=\"abloglink\" \
+    href=\"${{ blog_home_url | / }}/${{ blog_post_path }}\">${{ date_written }} - ${{ title }}</a></div>";
+
+const POST_TEMPLATE: &str = "<html>
+<head>
+    <title>${{ title }}</title>
+    <meta name=\"description\" content=\"${{ description | }}\">
+    ${% for tag in tags %}<meta property=\"article:tag\" content=\"${{ tag }}\">${% endfor %}
+</head>
+<body>
+    <h1>${{ title }}</h1>
+    ${% if author_name %}
+    <p>by ${{ author_name }} on ${{ date_written |> date(\"%B %d, %Y\") }}</p>
+    ${% endif %}
+    ${{ raw rendered_body }}
+</body>
+</html>
+";
+
+fn get_posts() -> Vec<HashMap<String, String>> {
+    (0..NUM_POSTS).map(|i| {
+        let mut post = HashMap::new();
+        post.insert("title".to_string(), format!("Post number {}", i));
+        post.insert("blog_post_path".to_string(), format!("post-number-{}/", i));
+        post.insert("date_written".to_string(), "2021-05-24".to_string());
+        post.insert("author_name".to_string(), "nikita".to_string());
+        post.insert("tags.0".to_string(), "rust".to_string());
+        post.insert("tags.1".to_string(), "blog".to_string());
+        post.insert("rendered_body".to_string(), "<p>hello</p>".repeat(50));
+        post
+    }).collect()
+}
+
+fn bench_post_links(c: &mut Criterion) {
+    let posts = get_posts();
+    let mut group = c.benchmark_group("post_links");
+    group.bench_function("replace_all_from", |b| b.iter(|| {
+        for post in &posts {
+            black_box(replace_all_from(POST_LINK_TEMPLATE, post, FailureMode::FM_default("".into()), None));
+        }
+    }));
+    group.bench_function("compiled", |b| b.iter(|| {
+        let template = Template::compile(POST_LINK_TEMPLATE).unwrap();
+        for post in &posts {
+            black_box(template.render(post, FailureMode::FM_default("".into()), &RenderOptions::default()));
+        }
+    }));
+    group.finish();
+}
+
+fn bench_posts(c: &mut Criterion) {
+    let posts = get_posts();
+    let options = RenderOptions { html_escape: true, ..Default::default() };
+    let mut group = c.benchmark_group("posts");
+    group.bench_function("render_template", |b| b.iter(|| {
+        for post in &posts {
+            black_box(render_template(POST_TEMPLATE, post, FailureMode::FM_default("".into()), &options).unwrap());
+        }
+    }));
+    group.bench_function("compiled", |b| b.iter(|| {
+        let template = Template::compile_with_options(POST_TEMPLATE, &options).unwrap();
+        for post in &posts {
+            black_box(template.render(post, FailureMode::FM_default("".into()), &options));
+        }
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_post_links, bench_posts);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+use super::{Context, Value};
+
+/// looks up a key in each context in order, and uses the first one
+/// that has it. eg: a post, then the site config, then the environment:
+/// ```
+/// let context = post.or(&site).or(&env);
+/// ```
+#[derive(Default)]
+pub struct ContextChain<'a> {
+    contexts: Vec<&'a dyn Context>,
+}
+
+impl<'a> ContextChain<'a> {
+    pub fn new() -> ContextChain<'a> {
+        ContextChain::default()
+    }
+
+    /// adds a context to the end of the chain, ie: it is
+    /// only used for keys that the other contexts dont have
+    pub fn or(mut self, context: &'a dyn Context) -> ContextChain<'a> {
+        self.contexts.push(context);
+        self
+    }
+}
+
+impl Context for ContextChain<'_> {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        self.contexts.iter().find_map(|c| c.get_value_from_key(key, syntax_char))
+    }
+
+    fn get_list_len_from_key(&self, key: &str, syntax_char: char) -> Option<usize> {
+        self.contexts.iter().find_map(|c| c.get_list_len_from_key(key, syntax_char))
+    }
+
+    /// the context that the value comes from decides if its html
+    fn is_raw_html(&self, key: &str, syntax_char: char) -> bool {
+        self.contexts.iter()
+            .find(|c| c.get_value_from_key(key, syntax_char).is_some())
+            .is_some_and(|c| c.is_raw_html(key, syntax_char))
+    }
+}
+
+/// keys that only exist in one scope, on top of another context. the keys
+/// of the scope hide the keys of the parent with the same name, and
+/// every other key is looked up in the parent, eg:
+/// ```
+/// let mut scope = Scope::new(&post);
+/// scope.insert("toc_html", toc_html);
+/// ```
+pub struct Scope<'a> {
+    values: Value,
+    parent: &'a dyn Context,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(parent: &'a dyn Context) -> Scope<'a> {
+        Scope { values: Value::default(), parent }
+    }
+
+    /// the value can be anything that converts into a Value, eg: a list to loop over
+    pub fn insert(&mut self, key: &str, value: impl Into<Value>) {
+        self.values.insert(key, value);
+    }
+}
+
+impl Context for Scope<'_> {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        self.values.get_value_from_key(key, syntax_char)
+            .or_else(|| self.parent.get_value_from_key(key, syntax_char))
+    }
+
+    fn get_list_len_from_key(&self, key: &str, syntax_char: char) -> Option<usize> {
+        self.values.get_list_len_from_key(key, syntax_char)
+            .or_else(|| self.parent.get_list_len_from_key(key, syntax_char))
+    }
+
+    fn is_raw_html(&self, key: &str, syntax_char: char) -> bool {
+        match self.values.get_path(key) {
+            Some(_) => self.values.is_raw_html(key, syntax_char),
+            None => self.parent.is_raw_html(key, syntax_char),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+    use crate::{replace_all_from, render_template, FailureMode, RenderOptions, RawHtmlKeys};
+
+    #[test]
+    fn chains_fall_back_to_the_next_context() {
+        let mut post = HashMap::new();
+        post.insert("title", "my post");
+        let mut site = BTreeMap::new();
+        site.insert("title".to_string(), "my site");
+        site.insert("author".to_string(), "nikita");
+        let env = |key: &str| if key == "HOME" { Some("/home/nikita".to_string()) } else { None };
+        let context = post.or(&site).or(&env);
+        let text = "${{ title }} by ${{ author }} in ${{ HOME }} ${{ missing | none }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_panic, None);
+        assert_eq!(replaced, "my post by nikita in /home/nikita none");
+    }
+
+    #[test]
+    fn scopes_hide_their_parents_keys() {
+        let mut site = HashMap::new();
+        site.insert("title", "<b>my site</b>");
+        site.insert("body", "<p>site</p>");
+        let raw_keys = ["body"];
+        let site = RawHtmlKeys { context: &site, raw_keys: &raw_keys };
+        let mut scope = Scope::new(&site);
+        scope.insert("body", "<p>scope</p>");
+        scope.insert("tags", vec!["a", "b"]);
+        let text = "${{ title }} ${{ body }} ${% for tag in tags %}${{ tag }}${% endfor %}";
+        let options = RenderOptions { html_escape: true, ..Default::default() };
+        let rendered = render_template(text, &scope, FailureMode::FM_panic, &options);
+        // the body of the scope isnt marked as html, only the body of the site is
+        assert_eq!(rendered.unwrap(), "&lt;b&gt;my site&lt;/b&gt; &lt;p&gt;scope&lt;/p&gt; ab");
+    }
+}
@@ -0,0 +1,121 @@
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, time::Duration};
+use exechelper::{Capture, Exec};
+
+/// how long a command can run before it is killed, see CommandContext::timeout
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// runs `!{{ command }}` placeholders, and replaces them with what the
+/// command printed, eg: `!{{ git rev-parse --short HEAD }}`. the command is
+/// split on whitespace and run directly, not in a shell, so pipes
+/// and quotes dont work. if the command fails, or doesnt exit within
+/// the timeout, the key has no value.
+/// every command is only run once, no matter how often it's rendered.
+///
+/// this runs any command that a template contains, so only use it for
+/// templates that you trust. remember to add '!' to the valid syntax chars
+pub struct CommandContext {
+    syntax_char: char,
+    current_dir: Option<PathBuf>,
+    timeout: Duration,
+    outputs: RefCell<HashMap<String, Option<String>>>,
+}
+
+impl Default for CommandContext {
+    fn default() -> Self {
+        CommandContext {
+            syntax_char: '!',
+            current_dir: None,
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            outputs: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl CommandContext {
+    pub fn new() -> CommandContext {
+        CommandContext::default()
+    }
+
+    /// use another syntax char than '!'
+    pub fn with_syntax_char(mut self, syntax_char: char) -> Self {
+        self.syntax_char = syntax_char;
+        self
+    }
+
+    /// run the commands in this directory instead of the current one
+    pub fn current_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// a command that runs for longer than this is killed, so that
+    /// a stuck command cant stop the template from being rendered
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn run(&self, command: &str) -> Option<String> {
+        let exe_and_args: Vec<&str> = command.split_whitespace().collect();
+        let mut exec = Exec::from_args(&exe_and_args).ok()?
+            .stderr(Capture::Null)
+            .timeout(self.timeout);
+        if let Some(dir) = &self.current_dir {
+            exec = exec.current_dir(dir);
+        }
+        let output = exec.output().ok()?;
+        if !output.success() {
+            return None;
+        }
+        // most commands end their output with a newline
+        // that shouldnt end up in the template
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Some(stdout.trim_end_matches(&['\r', '\n'][..]).to_string())
+    }
+}
+
+impl super::Context for CommandContext {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        if syntax_char != self.syntax_char {
+            return None;
+        }
+        if let Some(output) = self.outputs.borrow().get(key) {
+            return output.clone();
+        }
+        let output = self.run(key);
+        self.outputs.borrow_mut().insert(key.to_string(), output.clone());
+        output
+    }
+
+    /// the output of a command is never a list
+    fn get_list_len_from_key(&self, _key: &str, _syntax_char: char) -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{replace_all_from, FailureMode};
+
+    #[test]
+    fn command_context_replaces_with_command_output() {
+        let context = CommandContext::new();
+        let text = "!{{ echo hello   world }} ${{ echo no }} !{{ false | failed }} !{{ not-a-real-command-cbvs | missing }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_ignore, Some("$!"));
+        assert_eq!(replaced, "hello world ${{ echo no }} failed missing");
+
+        let context = CommandContext::new().current_dir("/").with_syntax_char('#');
+        let replaced = replace_all_from("#{{ pwd }}", &context, FailureMode::FM_panic, Some("#"));
+        assert_eq!(replaced, "/");
+    }
+
+    #[test]
+    fn commands_that_dont_exit_are_killed() {
+        let context = CommandContext::new().timeout(Duration::from_millis(100));
+        let started = std::time::Instant::now();
+        let replaced = replace_all_from("!{{ sleep 5 | too slow }}", &context, FailureMode::FM_panic, Some("!"));
+        assert_eq!(replaced, "too slow");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+/// what happened to a placeholder that could not simply
+/// be replaced with the value of its key
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// the context didnt have the key, and there was no default,
+    /// so the failure mode decided what to replace it with
+    UnresolvedKey,
+    /// the context didnt have the key, so the default
+    /// from the template was used: `${{ key | default }}`
+    DefaultUsed(String),
+    /// the context didnt have the key, so the value of the
+    /// other key was used: `${{ key || other_key }}`
+    DefaultKeyUsed(String),
+    /// a filter doesnt exist or failed, so the failure mode
+    /// decided what to replace the placeholder with
+    FilterFailed(String),
+}
+
+/// where in the template a placeholder is. offset is in bytes, and
+/// line and column start at 1. file is None for the template itself,
+/// or the path of the file that was included
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Location {
+    pub file: Option<String>,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// the location at the start of a text
+    pub fn start(file: Option<String>) -> Location {
+        Location { file, offset: 0, line: 1, column: 1 }
+    }
+
+    /// the location after the text, if the text started at this location
+    pub fn advance(&self, text: &str) -> Location {
+        let mut location = self.clone();
+        location.offset += text.len();
+        match text.rfind('\n') {
+            Some(i) => {
+                location.line += text.matches('\n').count();
+                location.column = text[(i + 1)..].chars().count() + 1;
+            }
+            None => location.column += text.chars().count(),
+        }
+        location
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// the key without its default or filters
+    pub key: String,
+    /// the whole placeholder, eg: `${{ key | default }}`
+    pub placeholder: String,
+    pub kind: DiagnosticKind,
+    pub location: Location,
+}
+
+impl Diagnostic {
+    /// true if the placeholder was not replaced by a value from the
+    /// context or from its default, ie: the failure mode was used
+    pub fn is_unresolved(&self) -> bool {
+        matches!(self.kind, DiagnosticKind::UnresolvedKey | DiagnosticKind::FilterFailed(_))
+    }
+
+    /// like the Display impl, but uses template_name if
+    /// the diagnostic is in the template itself and not in an included file
+    pub fn to_string_with_template_name(&self, template_name: &str) -> String {
+        let file = self.location.file.as_deref().unwrap_or(template_name);
+        format!("{}:{}:{}: {}", file, self.location.line, self.location.column, self.describe())
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            DiagnosticKind::UnresolvedKey => format!("unknown key '{}'", self.key),
+            DiagnosticKind::DefaultUsed(default) => format!("unknown key '{}', used the default '{}'", self.key, default),
+            DiagnosticKind::DefaultKeyUsed(other) => format!("unknown key '{}', used the key '{}' instead", self.key, other),
+            DiagnosticKind::FilterFailed(err) => format!("{} in '{}'", err, self.placeholder),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with_template_name("template"))
+    }
+}
+
+/// the substituted text, and every placeholder that needed a default,
+/// or couldnt be replaced, in the order that they appear in the output
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Substituted {
+    pub text: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Substituted {
+    pub fn unresolved(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_unresolved())
+    }
+
+    /// the text, or an error if any placeholder could not be replaced
+    pub fn into_result(self) -> Result<String, SubstitutionError> {
+        let unresolved: Vec<Diagnostic> = self.unresolved().cloned().collect();
+        if unresolved.is_empty() {
+            Ok(self.text)
+        } else {
+            Err(SubstitutionError::MissingKeys(unresolved))
+        }
+    }
+}
+
+/// why a try_ function failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubstitutionError {
+    /// the template could not be parsed, eg: its blocks
+    /// are not balanced, or an include failed
+    Template(String),
+    /// the failure mode was FM_error, and these placeholders could not
+    /// be replaced, either because their key was not found, or a filter failed
+    MissingKeys(Vec<Diagnostic>),
+}
+
+impl SubstitutionError {
+    /// every key that was not found, without duplicates
+    pub fn missing_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = vec![];
+        if let SubstitutionError::MissingKeys(diagnostics) = self {
+            for d in diagnostics {
+                if !keys.contains(&d.key.as_str()) {
+                    keys.push(&d.key);
+                }
+            }
+        }
+        keys
+    }
+
+    /// like the Display impl, but uses template_name for
+    /// the placeholders that are in the template itself
+    pub fn to_string_with_template_name(&self, template_name: &str) -> String {
+        match self {
+            SubstitutionError::Template(e) => e.clone(),
+            SubstitutionError::MissingKeys(diagnostics) => {
+                let mut out = format!("Failed to find a value for: {}", self.missing_keys().join(", "));
+                for d in diagnostics {
+                    out.push_str("\n  ");
+                    out.push_str(&d.to_string_with_template_name(template_name));
+                }
+                out
+            }
+        }
+    }
+}
+
+impl fmt::Display for SubstitutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with_template_name("template"))
+    }
+}
+
+impl std::error::Error for SubstitutionError {}
@@ -0,0 +1,668 @@
+use std::cell::Cell;
+use std::path::Path;
+use super::{Syntax, DEFAULT_SYNTAX};
+use super::{Context, SizedContext, FailureMode, FailureModeEx, Segment, compile_segments, render_segments};
+use super::{Location, Substituted, SubstitutionError};
+use super::{HtmlState, scan_html_state};
+use super::{Filters, BUILTIN_FILTERS};
+
+/// if a template includes itself (directly or not), we would
+/// loop forever, so we stop after this many nested includes
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// the block syntax of the template engine. like the substitution
+/// syntax, it can be prefixed by any syntax char:
+/// ${% if key %}, ${% for item in list %}, ${% include file.html %}
+/// see Syntax for other delimiters
+pub fn capture_block_of_type(text: &str) -> regex::CaptureMatches<'static, '_> {
+    DEFAULT_SYNTAX.capture_blocks(text)
+}
+
+/// how render_template should render a template
+pub struct RenderOptions<'a> {
+    /// which syntax chars to substitute, see replace_all_from. None means only '$'
+    pub valid_syntax_chars: Option<&'a str>,
+    /// where `${% include file %}` looks for files, if None, then includes are an error
+    pub include_dir: Option<&'a Path>,
+    /// if true, the template is treated as html, and values are escaped
+    /// depending on if they are inserted into text, or into a tag/attribute.
+    /// values that are already html can be inserted as is with `${{ raw key }}`
+    pub html_escape: bool,
+    /// the filters that can be used with `${{ key |> filter }}`
+    pub filters: &'a Filters,
+    /// the delimiters of placeholders and blocks, see Syntax
+    pub syntax: &'a Syntax,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        RenderOptions {
+            valid_syntax_chars: None,
+            include_dir: None,
+            html_escape: false,
+            filters: &BUILTIN_FILTERS,
+            syntax: &DEFAULT_SYNTAX,
+        }
+    }
+}
+
+/// a template is parsed into a tree of nodes. text nodes are
+/// compiled into segments of text and ${{ }} placeholders, those are
+/// substituted when the node is rendered
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text {
+        segments: Vec<Segment>,
+    },
+    If {
+        key: String,
+        negate: bool,
+        syntax_char: char,
+        then_nodes: Vec<Node>,
+        else_nodes: Vec<Node>,
+    },
+    For {
+        item: String,
+        list_key: String,
+        syntax_char: char,
+        body: Vec<Node>,
+    },
+}
+
+enum Token {
+    Text(String, Location),
+    Block {
+        words: Vec<String>,
+        syntax_char: char,
+        line: usize,
+    },
+}
+
+/// where we are parsing from, so that errors can point to the right file
+struct ParseState<'a> {
+    /// None for the template itself, otherwise the path of the included file
+    file: Option<String>,
+    syntax: &'a Syntax,
+    valid_syntax_chars: &'a str,
+    include_dir: Option<&'a Path>,
+    include_depth: usize,
+    html_state: Cell<HtmlState>,
+}
+
+impl ParseState<'_> {
+    fn err(&self, line: usize, msg: &str) -> String {
+        format!("{}:{}: {}", self.file.as_deref().unwrap_or("template"), line, msg)
+    }
+}
+
+/// if a block is the only thing on its line, we remove the whole
+/// line, otherwise every ${% if %} would leave behind an empty line
+fn get_standalone_block_span(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let line_start = text[0..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+    let before = &text[line_start..start];
+    let after = &text[end..line_end];
+    if before.trim().is_empty() && after.trim().is_empty() {
+        (line_start, line_end)
+    } else {
+        (start, end)
+    }
+}
+
+fn tokenize(text: &str, state: &ParseState) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut last_end = 0;
+    let mut location = Location::start(state.file.clone());
+    for cap in state.syntax.capture_blocks(text) {
+        let whole = cap.get(0).unwrap();
+        let syntax_char = cap[1].chars().next().unwrap();
+        if !state.valid_syntax_chars.contains(syntax_char) {
+            continue;
+        }
+        // an escaped block is text, without its escape char
+        let escape_start = whole.start() - state.syntax.escape_len(text, whole.start());
+        if state.syntax.is_escaped(text, whole.start()) {
+            if escape_start > last_end {
+                tokens.push(Token::Text(text[last_end..escape_start].to_string(), location.clone()));
+            }
+            location = location.advance(&text[last_end..whole.start()]);
+            tokens.push(Token::Text(whole.as_str().to_string(), location.clone()));
+            location = location.advance(whole.as_str());
+            last_end = whole.end();
+            continue;
+        }
+        let (start, end) = get_standalone_block_span(text, whole.start(), whole.end());
+        // a block after escaped escape chars isnt standalone, and only half of them are output
+        let start = if start == whole.start() { escape_start } else { start };
+        // a previous block on the same line might have already
+        // consumed some of the text we want to remove
+        let start = start.max(last_end);
+        if start > last_end {
+            tokens.push(Token::Text(text[last_end..start].to_string(), location.clone()));
+        }
+        location = location.advance(&text[last_end..whole.start()]);
+        tokens.push(Token::Block {
+            words: cap[2].split_whitespace().map(|w| w.to_string()).collect(),
+            syntax_char,
+            line: location.line,
+        });
+        location = location.advance(&text[whole.start()..end]);
+        last_end = end;
+    }
+    if last_end < text.len() {
+        tokens.push(Token::Text(text[last_end..].to_string(), location));
+    }
+    tokens
+}
+
+fn parse_include(state: &ParseState, path: &str, line: usize) -> Result<Vec<Node>, String> {
+    let path = path.trim_matches(|c| c == '"' || c == '\'');
+    let include_dir = match state.include_dir {
+        Some(d) => d,
+        None => return Err(state.err(line, &format!("Cannot include '{}' because no include directory was given", path))),
+    };
+    if state.include_depth >= MAX_INCLUDE_DEPTH {
+        return Err(state.err(line, &format!("Includes are nested more than {} times. Does '{}' include itself?", MAX_INCLUDE_DEPTH, path)));
+    }
+    let text = std::fs::read_to_string(include_dir.join(path))
+        .map_err(|e| state.err(line, &format!("Failed to include '{}': {}", path, e)))?;
+    let include_state = ParseState {
+        file: Some(path.to_string()),
+        syntax: state.syntax,
+        valid_syntax_chars: state.valid_syntax_chars,
+        include_dir: state.include_dir,
+        include_depth: state.include_depth + 1,
+        html_state: Cell::new(state.html_state.get()),
+    };
+    let mut tokens = tokenize(&text, &include_state).into_iter();
+    let (nodes, _) = parse_nodes(&include_state, &mut tokens, &[])?;
+    // the included text is part of the document, so we continue where it left off
+    state.html_state.set(include_state.html_state.get());
+    Ok(nodes)
+}
+
+/// the nodes, and which end word we stopped at (and its line)
+type ParsedNodes = (Vec<Node>, Option<(String, usize)>);
+
+/// parses tokens into nodes until we reach one of the end_words
+fn parse_nodes(
+    state: &ParseState,
+    tokens: &mut impl Iterator<Item = Token>,
+    end_words: &[&str],
+) -> Result<ParsedNodes, String> {
+    let mut nodes = vec![];
+    while let Some(token) = tokens.next() {
+        let (words, syntax_char, line) = match token {
+            Token::Text(text, location) => {
+                let html_state = state.html_state.get();
+                state.html_state.set(scan_html_state(html_state, &text));
+                let segments = compile_segments(&text, state.syntax, Some(state.valid_syntax_chars), html_state, &location);
+                nodes.push(Node::Text { segments });
+                continue;
+            }
+            Token::Block { words, syntax_char, line } => (words, syntax_char, line),
+        };
+        let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+        match words[..] {
+            [word] if end_words.contains(&word) => {
+                return Ok((nodes, Some((word.to_string(), line))));
+            }
+            [word @ ("else" | "endif" | "endfor")] => {
+                return Err(state.err(line, &format!("Unexpected '{}'", word)));
+            }
+            ["if", key] | ["if", "not", key] => {
+                let negate = words.len() == 3;
+                let (then_nodes, end) = parse_nodes(state, tokens, &["else", "endif"])?;
+                let else_nodes = match end {
+                    Some((ref w, _)) if w == "else" => {
+                        match parse_nodes(state, tokens, &["endif"])? {
+                            (else_nodes, Some(_)) => else_nodes,
+                            (_, None) => return Err(state.err(line, "Missing ${% endif %}")),
+                        }
+                    }
+                    Some(_) => vec![],
+                    None => return Err(state.err(line, "Missing ${% endif %}")),
+                };
+                nodes.push(Node::If { key: key.to_string(), negate, syntax_char, then_nodes, else_nodes });
+            }
+            ["for", item, "in", list_key] => {
+                let (body, end) = parse_nodes(state, tokens, &["endfor"])?;
+                if end.is_none() {
+                    return Err(state.err(line, "Missing ${% endfor %}"));
+                }
+                nodes.push(Node::For {
+                    item: item.to_string(), list_key: list_key.to_string(), syntax_char, body
+                });
+            }
+            ["include", path] => {
+                nodes.extend(parse_include(state, path, line)?);
+            }
+            _ => {
+                return Err(state.err(line, &format!("Unknown block '{}'", words.join(" "))));
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+/// parse the text of a template into nodes. only the syntax, valid_syntax_chars
+/// and include_dir of the options are used. include_dir is where
+/// `${% include file %}` looks for files, if None, then includes are an error
+pub fn parse_template(text: &str, options: &RenderOptions) -> Result<Vec<Node>, String> {
+    let state = ParseState {
+        file: None,
+        syntax: options.syntax,
+        valid_syntax_chars: options.valid_syntax_chars.unwrap_or("$"),
+        include_dir: options.include_dir,
+        include_depth: 0,
+        html_state: Cell::new(HtmlState::Text),
+    };
+    let mut tokens = tokenize(text, &state).into_iter();
+    let (nodes, _) = parse_nodes(&state, &mut tokens, &[])?;
+    Ok(nodes)
+}
+
+/// a template that is parsed once, and can then be rendered many times,
+/// eg: once for every blog post. the placeholders are found and their keys are
+/// parsed when compiling, so rendering only has to look up the values.
+/// the syntax, valid_syntax_chars and include_dir of the RenderOptions are used
+/// when compiling, html_escape and filters are used when rendering
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub nodes: Vec<Node>,
+}
+
+impl Template {
+    /// compiles with the default options: only '$' is a syntax char, and no includes
+    pub fn compile(text: &str) -> Result<Template, String> {
+        Template::compile_with_options(text, &RenderOptions::default())
+    }
+
+    /// returns an error if the blocks are not balanced, or if an include fails
+    pub fn compile_with_options(text: &str, options: &RenderOptions) -> Result<Template, String> {
+        let nodes = parse_template(text, options)?;
+        Ok(Template { nodes })
+    }
+
+    /// like render_template, but cant fail because the template was already parsed
+    pub fn render(&self, context: &(impl Context + ?Sized), failure_mode: FailureMode, options: &RenderOptions) -> String {
+        let mut failure_mode: FailureModeEx<fn(&String) -> Option<String>> = failure_mode.into();
+        self.render_actual(&SizedContext(context), &mut failure_mode, options).text
+    }
+
+    /// like render_template_ex
+    pub fn render_ex<F: FnMut(&String) -> Option<String>>(
+        &self,
+        context: &(impl Context + ?Sized),
+        failure_mode: FailureModeEx<F>,
+        options: &RenderOptions,
+    ) -> String {
+        let mut failure_mode = failure_mode;
+        self.render_actual(&SizedContext(context), &mut failure_mode, options).text
+    }
+
+    /// like render_template_with_diagnostics
+    pub fn render_with_diagnostics(
+        &self,
+        context: &(impl Context + ?Sized),
+        failure_mode: FailureMode,
+        options: &RenderOptions,
+    ) -> Substituted {
+        let mut failure_mode: FailureModeEx<fn(&String) -> Option<String>> = failure_mode.into();
+        self.render_actual(&SizedContext(context), &mut failure_mode, options)
+    }
+
+    /// like try_render_template
+    pub fn try_render(
+        &self,
+        context: &(impl Context + ?Sized),
+        failure_mode: FailureMode,
+        options: &RenderOptions,
+    ) -> Result<String, SubstitutionError> {
+        let strict = matches!(failure_mode, FailureMode::FM_error);
+        let rendered = self.render_with_diagnostics(context, failure_mode, options);
+        if strict { rendered.into_result() } else { Ok(rendered.text) }
+    }
+
+    fn render_actual<F: FnMut(&String) -> Option<String>>(
+        &self,
+        context: &dyn Context,
+        failure_mode: &mut FailureModeEx<F>,
+        options: &RenderOptions,
+    ) -> Substituted {
+        let mut out = Substituted::default();
+        render_nodes(&self.nodes, context, failure_mode, options, &mut out);
+        out
+    }
+}
+
+/// a key is true if it is a non empty list, or if it isnt a list
+/// and has a value that is not empty and not "false"
+pub fn is_truthy(context: &dyn Context, key: &str, syntax_char: char) -> bool {
+    if let Some(len) = context.get_list_len_from_key(key, syntax_char) {
+        if len > 0 {
+            return true;
+        }
+    }
+    match context.get_value_from_key(key, syntax_char) {
+        Some(value) => !value.is_empty() && value != "false",
+        None => false,
+    }
+}
+
+/// the context inside of a ${% for item in list %} loop.
+/// `item` resolves to `list.N`, and `item.title` resolves to `list.N.title`.
+/// there is also `loop.index` (starting at 1), `loop.index0`,
+/// `loop.first` and `loop.last`. everything else is looked up in the parent
+struct LoopContext<'a> {
+    parent: &'a dyn Context,
+    item: &'a str,
+    list_key: &'a str,
+    index: usize,
+    len: usize,
+}
+
+impl LoopContext<'_> {
+    fn get_parent_key(&self, key: &str) -> Option<String> {
+        if key == self.item {
+            return Some(format!("{}.{}", self.list_key, self.index));
+        }
+        key.strip_prefix(self.item)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .map(|rest| format!("{}.{}.{}", self.list_key, self.index, rest))
+    }
+}
+
+impl Context for LoopContext<'_> {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        let bool_str = |b: bool| if b { "true".to_string() } else { "false".to_string() };
+        match key {
+            "loop.index" => return Some((self.index + 1).to_string()),
+            "loop.index0" => return Some(self.index.to_string()),
+            "loop.first" => return Some(bool_str(self.index == 0)),
+            "loop.last" => return Some(bool_str(self.index + 1 == self.len)),
+            _ => {}
+        }
+        match self.get_parent_key(key) {
+            Some(parent_key) => self.parent.get_value_from_key(&parent_key, syntax_char),
+            None => self.parent.get_value_from_key(key, syntax_char),
+        }
+    }
+
+    fn get_list_len_from_key(&self, key: &str, syntax_char: char) -> Option<usize> {
+        match self.get_parent_key(key) {
+            Some(parent_key) => self.parent.get_list_len_from_key(&parent_key, syntax_char),
+            None => self.parent.get_list_len_from_key(key, syntax_char),
+        }
+    }
+
+    fn is_raw_html(&self, key: &str, syntax_char: char) -> bool {
+        match self.get_parent_key(key) {
+            Some(parent_key) => self.parent.is_raw_html(&parent_key, syntax_char),
+            None => self.parent.is_raw_html(key, syntax_char),
+        }
+    }
+}
+
+fn render_nodes<F: FnMut(&String) -> Option<String>>(
+    nodes: &[Node],
+    context: &dyn Context,
+    failure_mode: &mut FailureModeEx<F>,
+    options: &RenderOptions,
+    out: &mut Substituted,
+) {
+    for node in nodes {
+        match node {
+            Node::Text { segments } => {
+                render_segments(segments, context, failure_mode, options.filters, options.html_escape, out);
+            }
+            Node::If { key, negate, syntax_char, then_nodes, else_nodes } => {
+                let use_nodes = if is_truthy(context, key, *syntax_char) != *negate {
+                    then_nodes
+                } else {
+                    else_nodes
+                };
+                render_nodes(use_nodes, context, failure_mode, options, out);
+            }
+            Node::For { item, list_key, syntax_char, body } => {
+                let len = context.get_list_len_from_key(list_key, *syntax_char).unwrap_or(0);
+                for index in 0..len {
+                    let loop_context = LoopContext {
+                        parent: context, item, list_key, index, len,
+                    };
+                    render_nodes(body, &loop_context, failure_mode, options, out);
+                }
+            }
+        }
+    }
+}
+
+/// Like replace_all_from_ex, but the text can also contain blocks:
+/// ```
+/// ${% if key %} ... ${% else %} ... ${% endif %}
+/// ${% if not key %} ... ${% endif %}
+/// ${% for item in list %} ${{ loop.index }}: ${{ item.title }} ${% endfor %}
+/// ${% include partial.html %}
+/// ```
+/// where a list is any key that the context can give a length for
+/// (see `Context::get_list_len_from_key`). Returns an error if
+/// the blocks are not balanced, or if an include fails.
+/// see RenderOptions for html escaping, includes, and filters
+pub fn render_template_ex<F: FnMut(&String) -> Option<String>>(
+    text: &str,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureModeEx<F>,
+    options: &RenderOptions,
+) -> Result<String, String> {
+    let mut failure_mode = failure_mode;
+    render_template_actual(text, &SizedContext(context), &mut failure_mode, options).map(|out| out.text)
+}
+
+/// Like render_template, but also returns the diagnostics of every placeholder
+/// that used a default or the failure mode, see `replace_all_from_with_diagnostics`.
+/// placeholders inside of a loop have a diagnostic for every iteration
+pub fn render_template_with_diagnostics(
+    text: &str,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureMode,
+    options: &RenderOptions,
+) -> Result<Substituted, String> {
+    let mut failure_mode: FailureModeEx<fn(&String) -> Option<String>> = failure_mode.into();
+    render_template_actual(text, &SizedContext(context), &mut failure_mode, options)
+}
+
+/// Like render_template, but the failure mode can be FM_error, which returns an
+/// error that lists every placeholder that could not be replaced, see try_replace_all_from
+pub fn try_render_template(
+    text: &str,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureMode,
+    options: &RenderOptions,
+) -> Result<String, SubstitutionError> {
+    let template = Template::compile_with_options(text, options).map_err(SubstitutionError::Template)?;
+    template.try_render(context, failure_mode, options)
+}
+
+fn render_template_actual<F: FnMut(&String) -> Option<String>>(
+    text: &str,
+    context: &dyn Context,
+    failure_mode: &mut FailureModeEx<F>,
+    options: &RenderOptions,
+) -> Result<Substituted, String> {
+    let template = Template::compile_with_options(text, options)?;
+    Ok(template.render_actual(context, failure_mode, options))
+}
+
+/// Like render_template_ex, but with the simpler `FailureMode`
+pub fn render_template(
+    text: &str,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureMode,
+    options: &RenderOptions,
+) -> Result<String, String> {
+    let mut failure_mode_ex = failure_mode.into();
+    // see replace_all_from for why we do this
+    if false {
+        failure_mode_ex = FailureModeEx::FM_callback(|_| None);
+    }
+    render_template_ex(text, context, failure_mode_ex, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::RawHtmlKeys;
+
+    fn blog_context() -> HashMap<String, String> {
+        let mut context = HashMap::new();
+        context.insert("title".to_string(), "hello".to_string());
+        context.insert("empty".to_string(), "".to_string());
+        context.insert("tags.0".to_string(), "rust".to_string());
+        context.insert("tags.1".to_string(), "blog".to_string());
+        context.insert("posts.0.title".to_string(), "first".to_string());
+        context.insert("posts.1.title".to_string(), "second".to_string());
+        context.insert("posts.1.tags.0".to_string(), "nested".to_string());
+        context
+    }
+
+    #[test]
+    fn if_else_works() {
+        let context = blog_context();
+        let text = "${% if title %}yes${% else %}no${% endif %} ${% if empty %}yes${% else %}no${% endif %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "yes no");
+        let text = "${% if not missing %}not missing${% endif %}${% if tags %}, has tags${% endif %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "not missing, has tags");
+    }
+
+    #[test]
+    fn contexts_with_a_value_for_every_key_arent_lists() {
+        let closure = |key: &str| Some(format!("<{}>", key));
+        let text = "${% if name %}hi ${{ name }}${% endif %}${% for x in name %}${{ x }}${% endfor %}";
+        let rendered = render_template(text, &closure, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "hi <name>");
+
+        struct Everything;
+        impl Context for Everything {
+            fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+                Some(key.to_string())
+            }
+        }
+        assert_eq!(Everything.get_list_len_from_key("name", '$'), None);
+        let rendered = render_template(text, &Everything, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "hi name");
+    }
+
+    #[test]
+    fn for_loops_work() {
+        let context = blog_context();
+        let text = "${% for tag in tags %}${{ loop.index }}:${{ tag }}${% if not loop.last %}, ${% endif %}${% endfor %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "1:rust, 2:blog");
+
+        let text = "${% for post in posts %}${{ post.title }}(${% for tag in post.tags %}${{ tag }}${% endfor %}) ${% endfor %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "first() second(nested) ");
+
+        let text = "${% for x in missing %}${{ x }}${% endfor %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "");
+    }
+
+    #[test]
+    fn standalone_blocks_remove_their_line() {
+        let context = blog_context();
+        let text = "<ul>\n    ${% for tag in tags %}\n    <li>${{ tag }}</li>\n    ${% endfor %}\n</ul>\n";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "<ul>\n    <li>rust</li>\n    <li>blog</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn unbalanced_blocks_are_errors() {
+        let context = blog_context();
+        let err = render_template("a\n${% if title %}", &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap_err();
+        assert_eq!(err, "template:2: Missing ${% endif %}");
+        let err = render_template("${% endfor %}", &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap_err();
+        assert_eq!(err, "template:1: Unexpected 'endfor'");
+        let err = render_template("${% include x.html %}", &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap_err();
+        assert!(err.contains("no include directory"));
+    }
+
+    #[test]
+    fn includes_work() {
+        let dir = std::env::temp_dir().join(format!("cbvs-include-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tag.html"), "<b>${{ tag }}</b>").unwrap();
+        std::fs::write(dir.join("loop.html"), "${% include loop.html %}").unwrap();
+        std::fs::write(dir.join("missing.html"), "\n  ${{ missing }}").unwrap();
+        let context = blog_context();
+        let include_options = RenderOptions { include_dir: Some(&dir), ..Default::default() };
+        let text = "${% for tag in tags %}${% include tag.html %}${% endfor %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &include_options);
+        let err = render_template("${% include loop.html %}", &context, FailureMode::FM_panic, &include_options);
+        let text = "${% if title %}\n${{ nope }}\n${% endif %}\n${% include missing.html %}";
+        let with_diagnostics = render_template_with_diagnostics(
+            text, &context, FailureMode::FM_default("".into()), &include_options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rendered.unwrap(), "<b>rust</b><b>blog</b>");
+        assert!(err.unwrap_err().contains("Does 'loop.html' include itself?"));
+        let diagnostics: Vec<String> = with_diagnostics.diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, vec!["template:2:1: unknown key 'nope'", "missing.html:2:3: unknown key 'missing'"]);
+    }
+
+    #[test]
+    fn compiled_templates_can_be_rendered_many_times() {
+        let template = Template::compile("${% for tag in tags %}<b>${{ tag |> upper }}</b>${% endfor %}${{ title | none }}").unwrap();
+        let mut first = HashMap::new();
+        first.insert("tags.0", "a");
+        first.insert("tags.1", "<b>");
+        let mut second = HashMap::new();
+        second.insert("title", "hello");
+        let html_options = RenderOptions { html_escape: true, ..Default::default() };
+        assert_eq!(template.render(&first, FailureMode::FM_panic, &html_options), "<b>A</b><b>&lt;B&gt;</b>none");
+        assert_eq!(template.render(&second, FailureMode::FM_panic, &html_options), "hello");
+        let rendered = template.render_with_diagnostics(&first, FailureMode::FM_panic, &RenderOptions::default());
+        assert_eq!(rendered.text, "<b>A</b><b><B></b>none");
+        assert_eq!(rendered.diagnostics[0].to_string(), "template:1:62: unknown key 'title', used the default 'none'");
+
+        let err = Template::compile("${% for tag in tags %}").unwrap_err();
+        assert_eq!(err, "template:1: Missing ${% endfor %}");
+    }
+
+    #[test]
+    fn try_render_returns_errors_instead_of_panicking() {
+        let context = blog_context();
+        let text = "${% for post in posts %}${{ post.title }} ${{ post.date }}${% endfor %}";
+        let err = try_render_template(text, &context, FailureMode::FM_error, &RenderOptions::default()).unwrap_err();
+        assert_eq!(err.missing_keys(), vec!["post.date"]);
+        assert!(matches!(err, SubstitutionError::MissingKeys(ref d) if d.len() == 2));
+        let err = try_render_template("${% if title %}", &context, FailureMode::FM_error, &RenderOptions::default()).unwrap_err();
+        assert_eq!(err, SubstitutionError::Template("template:1: Missing ${% endif %}".into()));
+        let template = Template::compile("${{ title }}").unwrap();
+        assert_eq!(template.try_render(&context, FailureMode::FM_error, &RenderOptions::default()).unwrap(), "hello");
+    }
+
+    #[test]
+    fn html_escaping_depends_on_where_the_value_is() {
+        let mut context = HashMap::new();
+        context.insert("title".to_string(), "\"quoted\" <b>".to_string());
+        context.insert("body".to_string(), "<p>html</p>".to_string());
+        let html_options = RenderOptions { html_escape: true, ..Default::default() };
+        let text = "<meta content=\"${{ title }}\"><h1>${{ title }}</h1>${{ raw body }}${{ body }}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &html_options).unwrap();
+        assert_eq!(rendered, "<meta content=\"&quot;quoted&quot; &lt;b&gt;\"><h1>\"quoted\" &lt;b&gt;</h1><p>html</p>&lt;p&gt;html&lt;/p&gt;");
+
+        // the html state carries over blocks, and a context can mark keys as html
+        let raw_keys = ["body"];
+        let raw_context = RawHtmlKeys { context: &context, raw_keys: &raw_keys };
+        let text = "<a title='${% if title %}${{ title }}${% endif %}'>${{ body | x }}</a>";
+        let rendered = render_template(text, &raw_context, FailureMode::FM_panic, &html_options).unwrap();
+        assert_eq!(rendered, "<a title='&quot;quoted&quot; &lt;b&gt;'><p>html</p></a>");
+
+        // without html escaping, the raw marker is still understood
+        let rendered = render_template("${{ raw body }}", &context, FailureMode::FM_panic, &RenderOptions::default()).unwrap();
+        assert_eq!(rendered, "<p>html</p>");
+    }
+}
@@ -0,0 +1,65 @@
+/// looks up `E{{ KEY }}` placeholders in the environment variables, eg:
+/// `E{{ HOME }}`. placeholders with any other syntax char are not
+/// looked up, so it can be chained before other contexts. remember
+/// to add 'E' to the valid syntax chars:
+/// ```
+/// let context = EnvContext::new().or(&post);
+/// ```
+pub struct EnvContext {
+    syntax_char: char,
+}
+
+impl Default for EnvContext {
+    fn default() -> Self {
+        EnvContext { syntax_char: 'E' }
+    }
+}
+
+impl EnvContext {
+    pub fn new() -> EnvContext {
+        EnvContext::default()
+    }
+
+    /// use another syntax char than 'E', eg: '%' for `%{{ HOME }}`
+    pub fn with_syntax_char(syntax_char: char) -> EnvContext {
+        EnvContext { syntax_char }
+    }
+}
+
+impl super::Context for EnvContext {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        if syntax_char != self.syntax_char {
+            return None;
+        }
+        std::env::var(key).ok()
+    }
+
+    /// environment variables are never lists
+    fn get_list_len_from_key(&self, _key: &str, _syntax_char: char) -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{replace_all_from, Context, FailureMode};
+
+    #[test]
+    fn env_context_only_replaces_its_syntax_char() {
+        std::env::set_var("CBVS_TEST_ENV_VAR", "hello");
+        let mut context = HashMap::new();
+        context.insert("CBVS_TEST_ENV_VAR", "not from env");
+        let env = EnvContext::new();
+        // most contexts dont check the syntax char, so the env goes first
+        let context = env.or(&context);
+        let text = "E{{ CBVS_TEST_ENV_VAR }} ${{ CBVS_TEST_ENV_VAR }} E{{ CBVS_TEST_MISSING_VAR | none }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_panic, Some("$E"));
+        assert_eq!(replaced, "hello not from env none");
+
+        let env = EnvContext::with_syntax_char('%');
+        let replaced = replace_all_from("%{{ CBVS_TEST_ENV_VAR }}", &env, FailureMode::FM_panic, Some("%"));
+        assert_eq!(replaced, "hello");
+    }
+}
@@ -0,0 +1,106 @@
+use super::Context;
+
+/// `${{ raw key }}` inserts the value of key without escaping it
+pub const RAW_MARKER: &str = "raw ";
+
+/// returns the key without the raw marker, and whether it had one
+pub fn strip_raw_marker(key: &str) -> (&str, bool) {
+    match key.strip_prefix(RAW_MARKER) {
+        Some(k) => (k.trim_start(), true),
+        None => (key, false),
+    }
+}
+
+/// where we are in an html document. this decides
+/// how a value needs to be escaped when it is inserted there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HtmlState {
+    /// between tags, eg: `<p>here</p>`
+    Text,
+    /// inside of a tag, but not in a quoted value, eg: `<a here>`
+    Tag,
+    /// inside of a quoted attribute value, eg: `<a href="here">`
+    AttributeValue(char),
+}
+
+/// the state of the html document after the given text,
+/// if the text started in the given state. this only needs to be good
+/// enough to know if we are inside of a tag or an attribute, so comments
+/// and things like <script> are treated as regular tags and text
+pub fn scan_html_state(state: HtmlState, text: &str) -> HtmlState {
+    let mut state = state;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        state = match (state, c) {
+            // a '<' thats not followed by a tag name
+            // is just a less than sign, eg: `1 < 2`
+            (HtmlState::Text, '<') => match chars.peek() {
+                Some(next) if next.is_ascii_alphabetic() || *next == '/' || *next == '!' => HtmlState::Tag,
+                _ => HtmlState::Text,
+            },
+            (HtmlState::Tag, '>') => HtmlState::Text,
+            (HtmlState::Tag, '"') | (HtmlState::Tag, '\'') => HtmlState::AttributeValue(c),
+            (HtmlState::AttributeValue(quote), c) if c == quote => HtmlState::Tag,
+            (s, _) => s,
+        };
+    }
+    state
+}
+
+pub fn escape_html_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// quotes are escaped too, so that the value cant end the attribute early
+pub fn escape_html_attribute(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// escape a value depending on where in the html document it is being inserted
+pub fn escape_html_for_state(state: HtmlState, text: &str) -> String {
+    match state {
+        HtmlState::Text => escape_html_text(text),
+        HtmlState::Tag | HtmlState::AttributeValue(_) => escape_html_attribute(text),
+    }
+}
+
+/// wraps a context, and marks some of its keys as html
+/// that should not be escaped, eg: a rendered markdown document
+pub struct RawHtmlKeys<'a, C: Context + ?Sized> {
+    pub context: &'a C,
+    pub raw_keys: &'a [&'a str],
+}
+
+impl<C: Context + ?Sized> Context for RawHtmlKeys<'_, C> {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        self.context.get_value_from_key(key, syntax_char)
+    }
+
+    fn get_list_len_from_key(&self, key: &str, syntax_char: char) -> Option<usize> {
+        self.context.get_list_len_from_key(key, syntax_char)
+    }
+
+    fn is_raw_html(&self, key: &str, syntax_char: char) -> bool {
+        self.raw_keys.contains(&key) || self.context.is_raw_html(key, syntax_char)
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+
+/// `${{ key |> filter }}` passes the value of key through a filter.
+/// filters can be chained: `${{ key |> lower |> truncate(10) }}`
+pub const FILTER_SEPARATOR: &str = " |> ";
+
+/// a filter gets the value, and the arguments that were passed to it,
+/// eg: for `|> truncate(160)` the args are `["160"]`
+pub type Filter = Box<dyn Fn(&str, &[String]) -> Result<String, String> + Send + Sync>;
+
+/// the filters that can be used in a template, by name
+#[derive(Default)]
+pub struct Filters {
+    filters: HashMap<String, Filter>,
+}
+
+impl Filters {
+    /// a registry without any filters. see `Filters::builtin()`
+    /// if you want to add your own filters next to the builtin ones
+    pub fn new() -> Filters {
+        Filters::default()
+    }
+
+    /// upper, lower, date(format), truncate(n), urlencode, and xml_escape
+    pub fn builtin() -> Filters {
+        let mut filters = Filters::new();
+        filters.add("upper", |value, _| Ok(value.to_uppercase()));
+        filters.add("lower", |value, _| Ok(value.to_lowercase()));
+        filters.add("date", filter_date);
+        filters.add("truncate", filter_truncate);
+        filters.add("urlencode", |value, _| Ok(urlencode(value)));
+        filters.add("xml_escape", |value, _| Ok(xml_escape(value)));
+        filters
+    }
+
+    /// adds a filter, or replaces the filter of the same name
+    pub fn add(
+        &mut self,
+        name: &str,
+        filter: impl Fn(&str, &[String]) -> Result<String, String> + Send + Sync + 'static,
+    ) {
+        self.filters.insert(name.to_string(), Box::new(filter));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Filter> {
+        self.filters.get(name)
+    }
+
+    /// run the value through every filter in order
+    pub fn apply(&self, value: String, filter_calls: &[FilterCall]) -> Result<String, String> {
+        let mut value = value;
+        for call in filter_calls {
+            let filter = self.get(&call.name)
+                .ok_or_else(|| format!("Unknown filter '{}'", call.name))?;
+            value = filter(&value, &call.args)
+                .map_err(|e| format!("Filter '{}' failed: {}", call.name, e))?;
+        }
+        Ok(value)
+    }
+}
+
+lazy_static! {
+    /// used when no filters are given explicitly
+    pub static ref BUILTIN_FILTERS: Filters = Filters::builtin();
+}
+
+/// one `|> name(args)` of a placeholder
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterCall {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// args are separated by commas, and can be quoted
+/// if they contain commas, eg: `date("%B %d, %Y")`
+fn parse_filter_args(args: &str) -> Vec<String> {
+    let mut out = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+    for c in args.chars() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ',') => {
+                out.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() || !out.is_empty() {
+        out.push(current.trim().to_string());
+    }
+    out
+}
+
+pub fn parse_filter_call(text: &str) -> Result<FilterCall, String> {
+    let text = text.trim();
+    let (name, args) = match text.find('(') {
+        Some(i) => {
+            let args = text[(i + 1)..].strip_suffix(')')
+                .ok_or_else(|| format!("Missing ')' in filter '{}'", text))?;
+            (&text[0..i], parse_filter_args(args))
+        }
+        None => (text, vec![]),
+    };
+    if name.is_empty() {
+        return Err("Missing filter name after '|>'".into());
+    }
+    Ok(FilterCall { name: name.trim().to_string(), args })
+}
+
+/// splits `key | default |> upper |> truncate(3)` into
+/// the key part (`key | default`) and the filters
+pub fn split_filters(key: &str) -> Result<(&str, Vec<FilterCall>), String> {
+    let mut parts = key.split(FILTER_SEPARATOR);
+    // split always returns at least one part
+    let key = parts.next().unwrap_or(key);
+    let filter_calls = parts.map(parse_filter_call).collect::<Result<Vec<_>, _>>()?;
+    Ok((key, filter_calls))
+}
+
+fn get_single_arg<'a>(args: &'a [String], filter_name: &str) -> Result<&'a str, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(format!("{} takes exactly one argument, but got {}", filter_name, args.len())),
+    }
+}
+
+/// the value can be an rfc3339 date, a date like 2021-05-24, or a unix timestamp
+fn filter_date(value: &str, args: &[String]) -> Result<String, String> {
+    let format = get_single_arg(args, "date")?;
+    let value = value.trim();
+    let datetime = if let Ok(d) = chrono::DateTime::parse_from_rfc3339(value) {
+        d.naive_utc()
+    } else if let Ok(d) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        d.and_hms_opt(0, 0, 0).ok_or("invalid date")?
+    } else if let Ok(timestamp) = value.parse::<i64>() {
+        chrono::DateTime::from_timestamp(timestamp, 0).ok_or("invalid timestamp")?.naive_utc()
+    } else {
+        return Err(format!("'{}' is not a date", value));
+    };
+    let mut out = String::new();
+    use std::fmt::Write;
+    write!(out, "{}", datetime.format(format)).map_err(|_| format!("invalid date format '{}'", format))?;
+    Ok(out)
+}
+
+/// the output is at most n characters long, including the
+/// ellipsis that is added if the value was cut short
+fn filter_truncate(value: &str, args: &[String]) -> Result<String, String> {
+    let max_len: usize = get_single_arg(args, "truncate")?.parse()
+        .map_err(|_| "truncate takes a number".to_string())?;
+    if value.chars().count() <= max_len {
+        return Ok(value.to_string());
+    }
+    let mut out: String = value.chars().take(max_len.saturating_sub(1)).collect();
+    out.push('…');
+    Ok(out)
+}
+
+pub fn urlencode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+pub fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{replace_all_from, render_template, FailureMode, RenderOptions};
+
+    #[test]
+    fn builtin_filters_work() {
+        let mut context = HashMap::new();
+        context.insert("title", "Hello World");
+        context.insert("date", "2021-05-24T23:08:02+00:00");
+        context.insert("url", "a b&c/d");
+        let text = "${{ title |> upper }} ${{ title |> lower |> truncate(5) }} ${{ date |> date(\"%Y-%m-%d\") }} \
+            ${{ url |> urlencode }} ${{ missing | <x> |> xml_escape }} ${{ title |> truncate(20) }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_panic, None);
+        assert_eq!(replaced, "HELLO WORLD hell… 2021-05-24 a%20b%26c%2Fd &lt;x&gt; Hello World");
+    }
+
+    #[test]
+    fn failing_filters_use_the_failure_mode() {
+        let mut context = HashMap::new();
+        context.insert("title", "Hello World");
+        let text = "${{ title |> nope }} ${{ title |> truncate(x) }} ${{ title |> date(\"%Y\") }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_default("failed".into()), None);
+        assert_eq!(replaced, "failed failed failed");
+    }
+
+    #[test]
+    fn custom_filters_can_be_added() {
+        let mut filters = Filters::builtin();
+        filters.add("repeat", |value, args| {
+            let times: usize = args.first().ok_or("missing argument")?.parse().map_err(|_| "not a number")?;
+            Ok(value.repeat(times))
+        });
+        let mut context = HashMap::new();
+        context.insert("title", "ab");
+        let options = RenderOptions { filters: &filters, ..Default::default() };
+        let replaced = render_template("${{ title |> repeat(3) |> upper }}", &context, FailureMode::FM_panic, &options);
+        assert_eq!(replaced.unwrap(), "ABABAB");
+        assert_eq!(parse_filter_call("date(\"%B %d, %Y\")").unwrap().args, vec!["%B %d, %Y"]);
+    }
+}
@@ -1,23 +1,39 @@
-use std::{collections::HashMap, string::ToString, hash::Hash};
+use std::{collections::{BTreeMap, HashMap}, string::ToString, hash::Hash};
 use regex::Regex;
 use lazy_static::lazy_static;
 
-// TODO:
-// do I want this to be more dynamic?
-// right now it allows any sort of syntax character
-// like ${{ }} or !{{ }}, @{{ }}, etc..
-pub fn capture_parameter_of_type(text: &str) -> regex::CaptureMatches {
-    // first matching group: (\S) will capture any single non-whitespace character
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(\S)\{\{\x20(..*?)\x20\}\}").unwrap();
-    }
-    RE.captures_iter(text)
+mod engine;
+pub use engine::*;
+mod escape;
+pub use escape::*;
+mod filters;
+pub use filters::*;
+mod diagnostics;
+pub use diagnostics::*;
+mod syntax;
+pub use syntax::*;
+mod value;
+pub use value::*;
+mod chain;
+pub use chain::*;
+mod env;
+pub use env::*;
+mod stream;
+pub use stream::*;
+#[cfg(feature = "commands")]
+mod command;
+#[cfg(feature = "commands")]
+pub use command::*;
+
+/// captures the placeholders of the default syntax: any syntax
+/// character like ${{ }} or !{{ }}, @{{ }}, etc..
+/// see Syntax for other delimiters
+pub fn capture_parameter_of_type(text: &str) -> regex::CaptureMatches<'static, '_> {
+    DEFAULT_SYNTAX.capture_placeholders(text)
 }
 
-
-
-// same TODO as above.. should we allow custom/dynamic ways
-// to detect default variable substitutions?
+/// the default of a key is the same for every Syntax:
+/// `key | default value` or `key || default_key`
 pub fn capture_default(text: &str) -> regex::CaptureMatches {
     lazy_static! {
         static ref RE: Regex = Regex::new(r"(..*?)\x20(\|+)\x20(..*?)$").unwrap();
@@ -25,6 +41,7 @@ pub fn capture_default(text: &str) -> regex::CaptureMatches {
     RE.captures_iter(text)
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum DefaultType {
     DefaultNone,
     DefaultString(String, String),
@@ -62,6 +79,9 @@ pub fn try_get_default(key: &str) -> DefaultType {
     default_replace
 }
 
+/// the most keys that the default get_list_len_from_key will try
+pub const MAX_PROBED_LIST_LEN: usize = 10_000;
+
 /// a context can be defined on any data structure. The context trait allows the user
 /// to define what keys, and what syntax chars are relevant, and then how to
 /// get a value from a replacement key. Consider the example:
@@ -71,6 +91,74 @@ pub fn try_get_default(key: &str) -> DefaultType {
 /// return a string to replace
 pub trait Context {
     fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String>;
+
+    /// used by `${% for item in key %}` to know how many items to loop over.
+    /// by default a list is a series of keys: `key.0`, `key.1`, etc.
+    /// so we count how many of those keys we can get a value for, up to
+    /// MAX_PROBED_LIST_LEN. a context that has a value for all of them
+    /// probably has a value for any key, so its not a list.
+    /// contexts that know their lists up front should override this
+    fn get_list_len_from_key(&self, key: &str, syntax_char: char) -> Option<usize> {
+        let mut len = 0;
+        while self.get_value_from_key(&format!("{}.{}", key, len), syntax_char).is_some() {
+            len += 1;
+            if len >= MAX_PROBED_LIST_LEN {
+                return None;
+            }
+        }
+        if len == 0 { None } else { Some(len) }
+    }
+
+    /// when rendering html templates, values are escaped unless they are
+    /// supposed to be html already. a context can say which of its keys
+    /// are html, otherwise the template has to use `${{ raw key }}`
+    fn is_raw_html(&self, _key: &str, _syntax_char: char) -> bool {
+        false
+    }
+
+    /// a chain that looks up keys in this context first, and
+    /// then in the other one, see ContextChain
+    fn or<'a>(&'a self, other: &'a dyn Context) -> ContextChain<'a> where Self: Sized {
+        ContextChain::new().or(self).or(other)
+    }
+}
+
+/// lets a context that isnt Sized, eg: a slice,
+/// be passed on as a `&dyn Context`
+struct SizedContext<'a, C: Context + ?Sized>(&'a C);
+
+impl<C: Context + ?Sized> Context for SizedContext<'_, C> {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        self.0.get_value_from_key(key, syntax_char)
+    }
+
+    fn get_list_len_from_key(&self, key: &str, syntax_char: char) -> Option<usize> {
+        self.0.get_list_len_from_key(key, syntax_char)
+    }
+
+    fn is_raw_html(&self, key: &str, syntax_char: char) -> bool {
+        self.0.is_raw_html(key, syntax_char)
+    }
+}
+
+/// for contexts that store each item of a list as keys like:
+/// `posts.0.title`, `posts.0.date`, `posts.1.title`, ...
+/// the length of the list is one more than the highest index we find
+pub fn get_list_len_from_keys<'a>(keys: impl Iterator<Item = &'a str>, key: &str) -> Option<usize> {
+    let mut len = None;
+    for k in keys {
+        let rest = match k.strip_prefix(key).and_then(|r| r.strip_prefix('.')) {
+            Some(r) => r,
+            None => continue,
+        };
+        let index = rest.split('.').next().unwrap_or("");
+        if let Ok(index) = index.parse::<usize>() {
+            if Some(index + 1) > len {
+                len = Some(index + 1);
+            }
+        }
+    }
+    len
 }
 
 // some specific implementations we should make so that
@@ -81,19 +169,37 @@ pub trait Context {
 
 /// for this one, we assume that the key is something
 /// that can be parsed into a usize so that we can
-/// index the Vec<T>. we also ~~assume~~ require that T can be
+/// index the slice. we also ~~assume~~ require that T can be
 /// converted into a string value
-/// TODO: figure out how to make this work for anything of [T], or &[..]
-/// i think its something like this, but I havent gotten it to work yet:
-/// impl<'a, T> Context for T where T: AsRef<[&'a str]>
+impl<T: ToString> Context for [T] {
+    fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+        key.parse::<usize>().ok().and_then(|i| self.get(i)).map(|v| v.to_string())
+    }
+}
+
+/// same as the slice context
+impl<T: ToString, const N: usize> Context for [T; N] {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        self[..].get_value_from_key(key, syntax_char)
+    }
+}
+
+/// same as the slice context
 impl<T: ToString> Context for Vec<T> {
+    fn get_value_from_key(&self, key: &str, syntax_char: char) -> Option<String> {
+        self[..].get_value_from_key(key, syntax_char)
+    }
+}
+
+/// a closure that gets the value of a key, eg: `|key: &str| std::env::var(key).ok()`.
+/// we cant know which keys a closure has, so it has no lists
+impl<F: Fn(&str) -> Option<String>> Context for F {
     fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
-        if let Ok(key_usize) = key.parse::<usize>() { match key_usize < self.len() {
-            true => Some(self[key_usize].to_string()),
-            false => None,
-        } } else {
-            None
-        }
+        self(key)
+    }
+
+    fn get_list_len_from_key(&self, _key: &str, _syntax_char: char) -> Option<usize> {
+        None
     }
 }
 
@@ -104,6 +210,42 @@ impl<V: AsRef<str>> Context for HashMap<&str, V> {
             None => None,
         }
     }
+
+    fn get_list_len_from_key(&self, key: &str, _syntax_char: char) -> Option<usize> {
+        get_list_len_from_keys(self.keys().copied(), key)
+    }
+}
+
+/// same as the HashMap<&str, V> context, but useful when
+/// the keys are generated, eg: `tags.0`, `tags.1`
+impl<V: AsRef<str>> Context for HashMap<String, V> {
+    fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+        self.get(key).map(|val| val.as_ref().to_string())
+    }
+
+    fn get_list_len_from_key(&self, key: &str, _syntax_char: char) -> Option<usize> {
+        get_list_len_from_keys(self.keys().map(|k| k.as_str()), key)
+    }
+}
+
+impl<V: AsRef<str>> Context for BTreeMap<&str, V> {
+    fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+        self.get(key).map(|val| val.as_ref().to_string())
+    }
+
+    fn get_list_len_from_key(&self, key: &str, _syntax_char: char) -> Option<usize> {
+        get_list_len_from_keys(self.keys().copied(), key)
+    }
+}
+
+impl<V: AsRef<str>> Context for BTreeMap<String, V> {
+    fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+        self.get(key).map(|val| val.as_ref().to_string())
+    }
+
+    fn get_list_len_from_key(&self, key: &str, _syntax_char: char) -> Option<usize> {
+        get_list_len_from_keys(self.keys().map(|k| k.as_str()), key)
+    }
 }
 
 /// an enum of options of what to do if the replace all
@@ -112,6 +254,9 @@ impl<V: AsRef<str>> Context for HashMap<&str, V> {
 pub enum FailureModeEx<F: FnMut(&String) -> Option<String>> {
     FM_ignore,
     FM_panic,
+    /// like FM_ignore, but the try_ functions return
+    /// a SubstitutionError with every key that was not found
+    FM_error,
     FM_default(String),
     FM_callback(F),
 }
@@ -122,6 +267,9 @@ pub enum FailureModeEx<F: FnMut(&String) -> Option<String>> {
 pub enum FailureMode {
     FM_ignore,
     FM_panic,
+    /// like FM_ignore, but the try_ functions return
+    /// a SubstitutionError with every key that was not found
+    FM_error,
     FM_default(String),
 }
 
@@ -130,6 +278,7 @@ impl<F: FnMut(&String) -> Option<String>> From<FailureMode> for FailureModeEx<F>
         match orig {
             FailureMode::FM_ignore => FailureModeEx::FM_ignore,
             FailureMode::FM_panic => FailureModeEx::FM_panic,
+            FailureMode::FM_error => FailureModeEx::FM_error,
             FailureMode::FM_default(s) => FailureModeEx::FM_default(s),
         }
     }
@@ -167,7 +316,7 @@ impl<F: FnMut(&String) -> Option<String>> From<FailureMode> for FailureModeEx<F>
 /// ```
 pub fn replace_all_from(
     text: &str,
-    context: &impl Context,
+    context: &(impl Context + ?Sized),
     failure_mode: FailureMode,
     valid_syntax_chars: Option<&str>,
 ) -> String {
@@ -189,89 +338,296 @@ pub fn replace_all_from(
 /// to replace a key if not found.
 pub fn replace_all_from_ex<F: FnMut(&String) -> Option<String>>(
     text: &str,
-    context: &impl Context,
+    context: &(impl Context + ?Sized),
     failure_mode: FailureModeEx<F>,
     valid_syntax_chars: Option<&str>,
 ) -> String {
     let mut failure_mode = failure_mode;
-    let mut replacements = vec![];
-    let mut s: String = text.clone().to_string();
+    replace_all_from_actual(text, &SizedContext(context), &mut failure_mode, valid_syntax_chars).text
+}
+
+/// Like replace_all_from, but also returns the diagnostics: every placeholder
+/// that was not replaced by the value of its key, ie: it used a default, or the
+/// failure mode, along with where it is in the text (byte offset, line and column).
+/// this is why there is no callback failure mode here, the diagnostics
+/// already tell you every key that was not found
+pub fn replace_all_from_with_diagnostics(
+    text: &str,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureMode,
+    valid_syntax_chars: Option<&str>,
+) -> Substituted {
+    let mut failure_mode: FailureModeEx<fn(&String) -> Option<String>> = failure_mode.into();
+    replace_all_from_actual(text, &SizedContext(context), &mut failure_mode, valid_syntax_chars)
+}
+
+/// Like replace_all_from, but instead of panicking, the failure mode can be FM_error,
+/// which returns an error that lists every placeholder that could not be replaced.
+/// with the other failure modes, this never returns an error
+pub fn try_replace_all_from(
+    text: &str,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureMode,
+    valid_syntax_chars: Option<&str>,
+) -> Result<String, SubstitutionError> {
+    let strict = matches!(failure_mode, FailureMode::FM_error);
+    let replaced = replace_all_from_with_diagnostics(text, context, failure_mode, valid_syntax_chars);
+    if strict { replaced.into_result() } else { Ok(replaced.text) }
+}
+
+fn replace_all_from_actual<F: FnMut(&String) -> Option<String>>(
+    text: &str,
+    context: &dyn Context,
+    failure_mode: &mut FailureModeEx<F>,
+    valid_syntax_chars: Option<&str>,
+) -> Substituted {
+    let segments = compile_segments(text, &DEFAULT_SYNTAX, valid_syntax_chars, HtmlState::Text, &Location::start(None));
+    let mut out = Substituted { text: String::with_capacity(text.len()), diagnostics: vec![] };
+    render_segments(&segments, context, failure_mode, &BUILTIN_FILTERS, false, &mut out);
+    out
+}
+
+/// the key of a placeholder, parsed once so that
+/// rendering doesnt have to parse it again: `raw key | default |> filter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedKey {
+    /// the key without the raw marker, the default, or the filters
+    pub key: String,
+    pub default: DefaultType,
+    /// an error if the filters couldnt be parsed
+    pub filter_calls: Result<Vec<FilterCall>, String>,
+    /// if the template marked the key as `${{ raw key }}`
+    pub raw: bool,
+}
+
+pub fn parse_key(key: &str) -> ParsedKey {
+    // templates can mark html values with `raw`, but
+    // that only matters when we are escaping values
+    let (key, raw) = strip_raw_marker(key);
+    let (key, filter_calls) = match split_filters(key) {
+        Ok((key, filter_calls)) => (key, Ok(filter_calls)),
+        Err(e) => (key, Err(e)),
+    };
+    // we need to set key to the output of try_get_default
+    // because if the first capture group was formatted
+    // with a default, then the 'key' isnt actually the key
+    // we want.. it is: "key | default", so we need to parse out the actual "key"
+    let default = try_get_default(key);
+    let key = match default {
+        DefaultNone => key.into(),
+        DefaultString(ref k, _) => k.clone(),
+        DefaultKey(ref k, _) => k.clone(),
+    };
+    ParsedKey { key, default, filter_calls, raw }
+}
+
+/// the value of a key, or of its default if the context doesnt have the key.
+/// returns the value, and which default was used if any
+fn get_value_or_default(
+    parsed: &ParsedKey,
+    syntax_char: char,
+    context: &dyn Context,
+) -> (Option<String>, Option<DiagnosticKind>) {
+    // ask the provided context if
+    // the current key has a value to be replaced
+    if let Some(replace_with) = context.get_value_from_key(parsed.key.as_str(), syntax_char) {
+        return (Some(replace_with), None);
+    }
+
+    // if not, then try the default
+    match &parsed.default {
+        DefaultNone => (None, None),
+        DefaultString(_, default_value) => {
+            let used = DiagnosticKind::DefaultUsed(default_value.clone());
+            (Some(default_value.clone()), Some(used))
+        }
+        DefaultKey(_, try_key) => {
+            // dynamic key usage:
+            match context.get_value_from_key(try_key.as_str(), syntax_char) {
+                Some(value) => (Some(value), Some(DiagnosticKind::DefaultKeyUsed(try_key.clone()))),
+                None => (None, None),
+            }
+        }
+    }
+}
+
+/// what a single `${{ key }}` should be replaced with. value is None if
+/// the match should be left as is. kind is set if the value didnt come from the key
+pub(crate) struct Replacement {
+    pub value: Option<String>,
+    pub kind: Option<DiagnosticKind>,
+}
+
+/// figures out what a single `${{ key }}` should be replaced with. the key
+/// can have a default, and filters. if the context doesnt have a value for the key or the
+/// default, or a filter fails, the failure mode decides
+pub(crate) fn get_replacement<F: FnMut(&String) -> Option<String>>(
+    parsed: &ParsedKey,
+    syntax_char: char,
+    context: &dyn Context,
+    failure_mode: &mut FailureModeEx<F>,
+    filters: &Filters,
+) -> Replacement {
+    let kind = match &parsed.filter_calls {
+        Ok(filter_calls) => {
+            let (value, used_default) = get_value_or_default(parsed, syntax_char, context);
+            match value.map(|v| filters.apply(v, filter_calls)) {
+                Some(Ok(value)) => return Replacement { value: Some(value), kind: used_default },
+                // a filter that fails is treated like a key that wasnt found
+                Some(Err(e)) => DiagnosticKind::FilterFailed(e),
+                None => DiagnosticKind::UnresolvedKey,
+            }
+        }
+        // so is a filter that we cant parse
+        Err(e) => DiagnosticKind::FilterFailed(e.clone()),
+    };
+
+    // if that failed, then use the provided failure mode
+    let value = match *failure_mode {
+        FailureModeEx::FM_ignore | FailureModeEx::FM_error => None,
+        FailureModeEx::FM_panic => panic!("Failed to get context value from key: {}", parsed.key),
+        FailureModeEx::FM_default(ref default) => Some(default.clone()),
+        FailureModeEx::FM_callback(ref mut cb) => cb(&parsed.key),
+    };
+    Replacement { value, kind: Some(kind) }
+}
+
+/// a placeholder that was found when compiling a text into segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    /// the whole placeholder, eg: `${{ key | default }}`
+    pub text: String,
+    pub syntax_char: char,
+    pub key: ParsedKey,
+    /// where in the html document the placeholder is, in case its value needs to be escaped
+    pub html_state: HtmlState,
+    /// where the placeholder is in its template, for the diagnostics
+    pub location: Location,
+}
+
+/// a text is compiled into the text between placeholders, which is
+/// copied as is, and the placeholders, which are replaced by their values
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// finds every match in one pass from left to right, and parses its key.
+/// escaped matches are turned into text, without their escape char.
+/// html_state is the state of the html document at the start of the text, and
+/// location is where the text starts in its template. if the text isnt html,
+/// the html state is still tracked, it just isnt used when rendering
+pub(crate) fn compile_segments(
+    text: &str,
+    syntax: &Syntax,
+    valid_syntax_chars: Option<&str>,
+    html_state: HtmlState,
+    location: &Location,
+) -> Vec<Segment> {
     // by default only examine '$'
     let valid_chars = valid_syntax_chars.unwrap_or("$");
-    let valid_chars: Vec<char> = valid_chars.chars().collect();
-    for cap in capture_parameter_of_type(text) {
-        let replace_str = &cap[0];
-        let syntax_char = &cap[1];
-        let key = &cap[2];
+    let mut segments = vec![];
+    let mut html_state = html_state;
+    let mut location = location.clone();
+    let mut last_end = 0;
+    for cap in syntax.capture_placeholders(text) {
+        let whole = cap.get(0).unwrap();
         // I don't think its possible for chars.nth(0) to be None
         // because if we are here that means we DID find a match...
-        let syntax_char = syntax_char.chars().nth(0).unwrap();
+        let syntax_char = cap[1].chars().next().unwrap();
 
         // if the current matches syntax char
         // is not one of the valid ones provided, then
         // skip this capture
-        if ! valid_chars.contains(&syntax_char) {
+        if !valid_chars.contains(syntax_char) {
             continue;
         }
 
-        // we need to set key to the output of try_get_default
-        // because if the first capture group was formatted
-        // with a default, then the 'key' isnt actually the key
-        // we want.. it is: "key | default", so we need to parse out the actual "key"
-        let default_type = try_get_default(key);
-        let key = match default_type {
-            DefaultNone => key.into(),
-            DefaultString(ref k, _) => k.clone(),
-            DefaultKey(ref k, _) => k.clone(),
-        };
-
-        // ask the provided context if
-        // the current key has a value to be replaced
-        if let Some(replace_with) = context.get_value_from_key(key.as_str(), syntax_char) {
-            replacements.push((replace_str.to_owned(), replace_with));
+        let escape_start = whole.start() - syntax.escape_len(text, whole.start());
+        if syntax.is_escaped(text, whole.start()) {
+            let before = &text[last_end..escape_start];
+            segments.push(Segment::Text(format!("{}{}", before, whole.as_str())));
+            html_state = scan_html_state(html_state, &text[last_end..whole.end()]);
+            location = location.advance(&text[last_end..whole.end()]);
+            last_end = whole.end();
             continue;
         }
 
-        // if not, then try the default
-        match default_type {
-            DefaultNone => (),
-            DefaultString(_, default_value) => {
-                replacements.push((replace_str.to_owned(), default_value));
+        let before = &text[last_end..escape_start];
+        if !before.is_empty() {
+            segments.push(Segment::Text(before.to_string()));
+        }
+        location = location.advance(&text[last_end..whole.start()]);
+        html_state = scan_html_state(html_state, before);
+        last_end = whole.end();
+        segments.push(Segment::Placeholder(Placeholder {
+            text: whole.as_str().to_string(),
+            syntax_char,
+            key: parse_key(&cap[2]),
+            html_state,
+            location: location.clone(),
+        }));
+        location = location.advance(whole.as_str());
+    }
+    if last_end < text.len() {
+        segments.push(Segment::Text(text[last_end..].to_string()));
+    }
+    segments
+}
+
+/// substitutes the placeholders of compiled segments: the text between them
+/// is copied as is, and each placeholder is replaced by its value. values are
+/// never searched for matches, so a value that contains `${{ key }}` is inserted as is.
+/// if html_escape is true, values are escaped depending on where they are
+/// inserted (unless they are raw html). the failure mode is borrowed so that the
+/// template engine can use the same callback for every piece of text that it substitutes.
+/// every placeholder that used a default or failed is added to the diagnostics
+pub(crate) fn render_segments<F: FnMut(&String) -> Option<String>>(
+    segments: &[Segment],
+    context: &dyn Context,
+    failure_mode: &mut FailureModeEx<F>,
+    filters: &Filters,
+    html_escape: bool,
+    out: &mut Substituted,
+) {
+    for segment in segments {
+        let placeholder = match segment {
+            Segment::Text(text) => {
+                out.text.push_str(text);
                 continue;
-            },
-            DefaultKey(_, try_key) => {
-                // dynamic key usage:
-                if let Some(replace_with) = context.get_value_from_key(try_key.as_str(), syntax_char) {
-                    replacements.push((replace_str.to_owned(), replace_with));
-                    continue;
-                }
             }
+            Segment::Placeholder(placeholder) => placeholder,
+        };
+        let syntax_char = placeholder.syntax_char;
+        let replacement = get_replacement(&placeholder.key, syntax_char, context, failure_mode, filters);
+        if let Some(kind) = replacement.kind {
+            out.diagnostics.push(Diagnostic {
+                key: placeholder.key.key.clone(),
+                placeholder: placeholder.text.clone(),
+                kind,
+                location: placeholder.location.clone(),
+            });
         }
-
-        // if that failed, then use the provided failure mode
-        match failure_mode {
-            FailureModeEx::FM_ignore => (),
-            FailureModeEx::FM_panic => panic!("Failed to get context value from key: {}", key),
-            FailureModeEx::FM_default(ref default) => replacements.push((replace_str.to_owned(), default.clone())),
-            FailureModeEx::FM_callback(ref mut cb) => {
-                if let Some(replace_with) = cb(&key) {
-                    replacements.push((replace_str.to_owned(), replace_with));
-                }
+        let replace_with = match replacement.value {
+            Some(v) => v,
+            None => {
+                out.text.push_str(&placeholder.text);
+                continue;
             }
+        };
+        if html_escape && !is_raw_html_key(&placeholder.key, syntax_char, context) {
+            out.text.push_str(&escape_html_for_state(placeholder.html_state, &replace_with));
+        } else {
+            out.text.push_str(&replace_with);
         }
     }
+}
 
-    // perform all the replacements at the end
-    // the above loop just populates a replacement vec
-    // and its easier to iterate over this at the end
-    for (replace_str, replace_with) in replacements {
-        s = s.replace(&replace_str[..], &replace_with[..]);
-    }
-
-    // TODO:
-    // would be nice to change API to allow return option
-    // either it did replace, or it did not
-    s
+/// if the template marks the key as `${{ raw key }}`, or the
+/// context says the key is html, then its value should not be escaped
+fn is_raw_html_key(parsed: &ParsedKey, syntax_char: char, context: &dyn Context) -> bool {
+    parsed.raw || context.is_raw_html(&parsed.key, syntax_char)
 }
 
 #[cfg(test)]
@@ -465,22 +821,77 @@ mod tests {
         assert_eq!(expected.to_string(), replaced);
     }
 
-    // TODO:
-    // #[test]
-    // fn context_works_for_any_vec_slice_or_array() {
-    //     let context = ["abc", "xyz"];
-    //     let context_vec = vec!["abc", "xyz"];
-    //     let context_string: [String; 2] = ["abc".into(), "xyz".into()];
-    //     let context_vec_string: Vec<String> = vec!["abc".into(), "xyz".into()];
-    //     context.something();
-    //     context_vec.something();
-    //     context_string.something();
-    //     context_vec_string.something();
-
-    //     takes_context3(&context);
-    //     takes_context3(&context_vec);
-    //     takes_context3(&context_string);
-    //     takes_context3(&context_vec[..]);
-    //     takes_context3(&context_vec_string[..]);
-    // }
+    #[test]
+    fn values_are_not_substituted_again() {
+        let mut context = HashMap::new();
+        context.insert("a", "${{ b }}");
+        context.insert("b", "x");
+        let text = "${{ a }} ${{ b }} ${{ a }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_panic, None);
+        assert_eq!(replaced, "${{ b }} x ${{ b }}");
+
+        // a value that looks like a failed match that was ignored
+        // should also not change the failed match
+        let text = "${{ missing }} ${{ a }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_ignore, None);
+        assert_eq!(replaced, "${{ missing }} ${{ b }}");
+    }
+
+    #[test]
+    fn diagnostics_have_locations() {
+        let mut context = HashMap::new();
+        context.insert("a", "A");
+        let text = "a\n  ${{ x }} ${{ y | d }} ${{ z || a }} ${{ a |> nope }} ${{ a }}";
+        let replaced = replace_all_from_with_diagnostics(text, &context, FailureMode::FM_default("".into()), None);
+        assert_eq!(replaced.text, "a\n   d A  A");
+        let kinds: Vec<(&str, &DiagnosticKind, usize, usize, usize)> = replaced.diagnostics.iter()
+            .map(|d| (d.key.as_str(), &d.kind, d.location.offset, d.location.line, d.location.column)).collect();
+        assert_eq!(kinds, vec![
+            ("x", &DiagnosticKind::UnresolvedKey, 4, 2, 3),
+            ("y", &DiagnosticKind::DefaultUsed("d".into()), 13, 2, 12),
+            ("z", &DiagnosticKind::DefaultKeyUsed("a".into()), 26, 2, 25),
+            ("a", &DiagnosticKind::FilterFailed("Unknown filter 'nope'".into()), 40, 2, 39),
+        ]);
+        let unresolved: Vec<String> = replaced.unresolved().map(|d| d.to_string_with_template_name("t.html")).collect();
+        assert_eq!(unresolved, vec![
+            "t.html:2:3: unknown key 'x'",
+            "t.html:2:39: Unknown filter 'nope' in '${{ a |> nope }}'",
+        ]);
+    }
+
+    #[test]
+    fn error_mode_returns_every_missing_key() {
+        let mut context = HashMap::new();
+        context.insert("a", "A");
+        let text = "${{ a }} ${{ x }}\n${{ y | d }} ${{ z }} ${{ x }}";
+        let err = try_replace_all_from(text, &context, FailureMode::FM_error, None).unwrap_err();
+        assert_eq!(err.missing_keys(), vec!["x", "z"]);
+        assert_eq!(err.to_string_with_template_name("t.txt"), "Failed to find a value for: x, z\n  \
+            t.txt:1:10: unknown key 'x'\n  t.txt:2:14: unknown key 'z'\n  t.txt:2:23: unknown key 'x'");
+
+        // the other failure modes dont return errors, and
+        // the non try_ functions leave the keys as is
+        let replaced = try_replace_all_from(text, &context, FailureMode::FM_default("".into()), None);
+        assert_eq!(replaced.unwrap(), "A \nd  ");
+        let replaced = replace_all_from(text, &context, FailureMode::FM_error, None);
+        assert_eq!(replaced, "A ${{ x }}\nd ${{ z }} ${{ x }}");
+        assert_eq!(try_replace_all_from("${{ a }}", &context, FailureMode::FM_error, None).unwrap(), "A");
+    }
+
+    #[test]
+    fn context_works_for_any_vec_slice_or_array() {
+        let context = ["abc", "xyz"];
+        let context_vec = vec!["abc", "xyz"];
+        let context_string: [String; 2] = ["abc".into(), "xyz".into()];
+        let context_vec_string: Vec<String> = vec!["abc".into(), "xyz".into()];
+        fn takes_context3(context: &(impl Context + ?Sized)) -> String {
+            replace_all_from("${{ 1 }} ${{ 0 }} ${{ 2 | none }}", context, FailureMode::FM_panic, None)
+        }
+
+        assert_eq!(takes_context3(&context), "xyz abc none");
+        assert_eq!(takes_context3(&context_vec), "xyz abc none");
+        assert_eq!(takes_context3(&context_string), "xyz abc none");
+        assert_eq!(takes_context3(&context_vec[..]), "xyz abc none");
+        assert_eq!(takes_context3(&context_vec_string[..]), "xyz abc none");
+    }
 }
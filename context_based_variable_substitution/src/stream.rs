@@ -0,0 +1,185 @@
+use std::io::{self, Read, Write};
+use super::{
+    compile_segments, render_segments, Context, FailureMode, FailureModeEx, HtmlState, Location,
+    SizedContext, Substituted, SubstitutionError, BUILTIN_FILTERS, DEFAULT_SYNTAX,
+};
+
+/// how many bytes are read from the reader at a time
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// the longest placeholder that gets replaced when reading from a reader.
+/// a `{{` that is further back than this without being closed is written as it is,
+/// so that a stray `{{` doesnt make us hold on to the rest of the line
+pub const MAX_PLACEHOLDER_LEN: usize = 4 * 1024;
+
+/// Like try_replace_all_from, but the text is read from a reader, and the
+/// replaced text is written to a writer as it goes, so that the whole text never
+/// has to be in memory. placeholders that are split between two reads are still replaced.
+/// only the text that might be the start of a placeholder is kept until the next read,
+/// and thats never more than MAX_PLACEHOLDER_LEN bytes, so placeholders longer than that
+/// are left as they are.
+/// with FM_error, the whole text is still written, and then an error of kind
+/// InvalidData is returned that contains the SubstitutionError
+/// # Example:
+/// ```
+/// let reader = File::open("big_template.html")?;
+/// let writer = BufWriter::new(File::create("big_page.html")?);
+/// replace_all_from_reader(reader, writer, &context, FailureMode::FM_error, None)?;
+/// ```
+pub fn replace_all_from_reader(
+    reader: impl Read,
+    writer: impl Write,
+    context: &(impl Context + ?Sized),
+    failure_mode: FailureMode,
+    valid_syntax_chars: Option<&str>,
+) -> io::Result<()> {
+    let strict = matches!(failure_mode, FailureMode::FM_error);
+    let mut failure_mode: FailureModeEx<fn(&String) -> Option<String>> = failure_mode.into();
+    let context = &SizedContext(context);
+    let mut reader = reader;
+    let mut writer = writer;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    // bytes of a utf8 char that was split between two reads
+    let mut bytes = vec![];
+    let mut pending = String::new();
+    let mut location = Location::start(None);
+    let mut unresolved = vec![];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let at_end = read == 0;
+        bytes.extend_from_slice(&chunk[..read]);
+        let valid_len = match std::str::from_utf8(&bytes) {
+            Ok(s) => s.len(),
+            // the rest of the char will come with the next read
+            Err(e) if e.error_len().is_none() && !at_end => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let valid = String::from_utf8(bytes.drain(..valid_len).collect())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        pending.push_str(&valid);
+
+        let cut = if at_end { pending.len() } else { get_safe_cut(&pending) };
+        let segments = compile_segments(
+            &pending[..cut], &DEFAULT_SYNTAX, valid_syntax_chars, HtmlState::Text, &location);
+        let mut out = Substituted::default();
+        render_segments(&segments, context, &mut failure_mode, &BUILTIN_FILTERS, false, &mut out);
+        writer.write_all(out.text.as_bytes())?;
+        unresolved.extend(out.diagnostics.into_iter().filter(|d| d.is_unresolved()));
+        location = location.advance(&pending[..cut]);
+        pending.drain(..cut);
+        if at_end {
+            break;
+        }
+    }
+    writer.flush()?;
+
+    if strict && !unresolved.is_empty() {
+        let err = SubstitutionError::MissingKeys(unresolved);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+    }
+    Ok(())
+}
+
+/// how much of the text can be replaced without knowing what comes after it.
+/// placeholders cant span lines, so only the text after the last complete
+/// placeholder, or the last newline, can be the start of one that isnt complete yet.
+/// that starts with the syntax char before the first `{{`,
+/// and if there is no `{{` yet, the last few chars might be the start of one.
+/// a `{{` more than MAX_PLACEHOLDER_LEN bytes back doesnt count, which keeps
+/// the text that is held back, and so the text that gets scanned, small
+fn get_safe_cut(text: &str) -> usize {
+    let last_placeholder_end = DEFAULT_SYNTAX.capture_placeholders(text)
+        .last()
+        .map_or(0, |cap| cap.get(0).unwrap().end());
+    let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+    let safe = last_placeholder_end.max(line_start);
+    let mut search_from = safe;
+    let (open, keep_chars) = loop {
+        match text[search_from..].find("{{") {
+            Some(i) if text.len() - (search_from + i) > MAX_PLACEHOLDER_LEN => search_from += i + 1,
+            Some(i) => break (search_from + i, 2),
+            None => break (text.len(), 3),
+        }
+    };
+    let kept_len: usize = text[safe..open].chars().rev()
+        .take(keep_chars)
+        .map(|c| c.len_utf8())
+        .sum();
+    open - kept_len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::replace_all_from;
+
+    /// only returns a few bytes from every read, to split placeholders
+    /// and utf8 chars at every possible position
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        bytes_per_read: usize,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.bytes_per_read.min(self.data.len()).min(buf.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn placeholders_split_between_reads_are_replaced() {
+        let mut context = HashMap::new();
+        context.insert("name", "bob");
+        context.insert("ü", "ümlaut");
+        let text = "héllo ${{ name }}!${{ ü }} \\${{ name }} {{ not }} @{{ name }}\n\
+            ${{ missing | dëfault }}${{ name }}{{${{ name }}\n{\n${{ name }}";
+        let expected = replace_all_from(text, &context, FailureMode::FM_panic, None);
+        assert_eq!(expected, "héllo bob!ümlaut \\bob {{ not }} @{{ name }}\n\
+            dëfaultbob{{bob\n{\nbob");
+        for bytes_per_read in 1..20 {
+            let reader = SlowReader { data: text.as_bytes(), bytes_per_read };
+            let mut out = vec![];
+            replace_all_from_reader(reader, &mut out, &context, FailureMode::FM_panic, None).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "{} bytes per read", bytes_per_read);
+        }
+    }
+
+    #[test]
+    fn unclosed_braces_on_a_long_line_dont_hold_back_the_rest() {
+        let mut context = HashMap::new();
+        context.insert("name", "bob");
+        let text = format!("${{{{ name }}}} {{{{ {} ${{{{ name }}}} ${{{{ name", "a".repeat(4 * 1024 * 1024));
+        let expected = replace_all_from(&text, &context, FailureMode::FM_ignore, None);
+        assert!(expected.starts_with("bob {{ aaa"));
+        assert!(expected.ends_with("a bob ${{ name"));
+        let mut out = vec![];
+        replace_all_from_reader(text.as_bytes(), &mut out, &context, FailureMode::FM_ignore, None).unwrap();
+        assert!(out == expected.as_bytes());
+    }
+
+    #[test]
+    fn reader_errors_have_locations_of_the_whole_text() {
+        let context: HashMap<&str, &str> = HashMap::new();
+        let text = format!("{}\n  ${{{{ x }}}} ${{{{ y }}}}", "a".repeat(CHUNK_SIZE * 2));
+        let mut out = vec![];
+        let err = replace_all_from_reader(text.as_bytes(), &mut out, &context, FailureMode::FM_error, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(out, text.as_bytes());
+        let err = err.into_inner().unwrap().downcast::<SubstitutionError>().unwrap();
+        assert_eq!(err.to_string_with_template_name("t.txt"), "Failed to find a value for: x, y\n  \
+            t.txt:2:3: unknown key 'x'\n  \
+            t.txt:2:12: unknown key 'y'");
+
+        let invalid: &[u8] = &[b'a', 0xff, b'b'];
+        let err = replace_all_from_reader(invalid, &mut out, &context, FailureMode::FM_ignore, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,202 @@
+use regex::Regex;
+use lazy_static::lazy_static;
+
+/// how placeholders and blocks look in a template. the default is `${{ key }}`
+/// and `${% block %}` with exactly one space inside of the delimiters, where `$` can be
+/// any syntax char, and nothing can be escaped. see SyntaxBuilder to use other
+/// delimiters, an escape char, or to allow any amount of whitespace
+#[derive(Debug, Clone)]
+pub struct Syntax {
+    placeholder_re: Regex,
+    block_re: Regex,
+    escape_char: Option<char>,
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        // the default delimiters are valid, so this cant fail
+        SyntaxBuilder::new().build().unwrap()
+    }
+}
+
+lazy_static! {
+    /// used when no syntax is given explicitly
+    pub static ref DEFAULT_SYNTAX: Syntax = Syntax::default();
+}
+
+impl Syntax {
+    pub fn builder() -> SyntaxBuilder {
+        SyntaxBuilder::new()
+    }
+
+    /// the first capture group is the syntax char, and the second is the key
+    pub fn capture_placeholders<'r, 't>(&'r self, text: &'t str) -> regex::CaptureMatches<'r, 't> {
+        self.placeholder_re.captures_iter(text)
+    }
+
+    /// the first capture group is the syntax char, and the second is the block
+    pub fn capture_blocks<'r, 't>(&'r self, text: &'t str) -> regex::CaptureMatches<'r, 't> {
+        self.block_re.captures_iter(text)
+    }
+
+    /// how many escape chars are right before `start`
+    fn escape_count(&self, text: &str, start: usize) -> usize {
+        match self.escape_char {
+            Some(c) => text[0..start].chars().rev().take_while(|&x| x == c).count(),
+            None => 0,
+        }
+    }
+
+    /// if the placeholder or block that starts at `start` is
+    /// preceded by the escape char, it should be output as is.
+    /// two escape chars are an escaped escape char, so `\\${{ key }}` is not escaped
+    pub fn is_escaped(&self, text: &str, start: usize) -> bool {
+        self.escape_count(text, start) % 2 == 1
+    }
+
+    /// how many bytes of escape chars before the match at `start` are not output:
+    /// one of every pair, and the one that escapes the match
+    pub fn escape_len(&self, text: &str, start: usize) -> usize {
+        let len = self.escape_char.map_or(0, |c| c.len_utf8());
+        self.escape_count(text, start).div_ceil(2) * len
+    }
+}
+
+/// builds a Syntax, eg: for `$<< key >>` and `$<% block %>`, or to
+/// output placeholders as is when they start with a backslash:
+/// `Syntax::builder().escape_char(Some('\\')).build()?`.
+/// ```
+/// let syntax = Syntax::builder().delimiters("<<", ">>").block_delimiters("<%", "%>").build()?;
+/// ```
+pub struct SyntaxBuilder {
+    open: String,
+    close: String,
+    block_open: String,
+    block_close: String,
+    escape_char: Option<char>,
+    any_whitespace: bool,
+}
+
+impl Default for SyntaxBuilder {
+    fn default() -> Self {
+        SyntaxBuilder {
+            open: "{{".into(),
+            close: "}}".into(),
+            block_open: "{%".into(),
+            block_close: "%}".into(),
+            escape_char: None,
+            any_whitespace: false,
+        }
+    }
+}
+
+impl SyntaxBuilder {
+    pub fn new() -> SyntaxBuilder {
+        SyntaxBuilder::default()
+    }
+
+    /// what comes after the syntax char, and what ends the placeholder
+    pub fn delimiters(mut self, open: &str, close: &str) -> Self {
+        self.open = open.into();
+        self.close = close.into();
+        self
+    }
+
+    /// same as delimiters, but for `${% if key %}` blocks
+    pub fn block_delimiters(mut self, open: &str, close: &str) -> Self {
+        self.block_open = open.into();
+        self.block_close = close.into();
+        self
+    }
+
+    /// a placeholder or block that starts with the escape char is output as is,
+    /// without the escape char. None, the default, means that nothing can be escaped
+    pub fn escape_char(mut self, escape_char: Option<char>) -> Self {
+        self.escape_char = escape_char;
+        self
+    }
+
+    /// if true, `${{key}}` and `${{   key  }}` are placeholders too,
+    /// otherwise there has to be exactly one space inside of the delimiters
+    pub fn any_whitespace(mut self, any_whitespace: bool) -> Self {
+        self.any_whitespace = any_whitespace;
+        self
+    }
+
+    fn make_regex(&self, open: &str, close: &str) -> Result<Regex, String> {
+        if open.is_empty() || close.is_empty() {
+            return Err("Delimiters cannot be empty".into());
+        }
+        let space = if self.any_whitespace { r"\s*" } else { r"\x20" };
+        // first matching group: (\S) will capture any single non-whitespace character
+        let pattern = format!(r"(\S){}{}(..*?){}{}",
+            regex::escape(open), space, space, regex::escape(close));
+        Regex::new(&pattern).map_err(|e| e.to_string())
+    }
+
+    /// returns an error if a delimiter is empty, or if
+    /// placeholders and blocks start with the same delimiter
+    pub fn build(self) -> Result<Syntax, String> {
+        if self.open == self.block_open {
+            return Err(format!("Placeholders and blocks cannot both start with '{}'", self.open));
+        }
+        Ok(Syntax {
+            placeholder_re: self.make_regex(&self.open, &self.close)?,
+            block_re: self.make_regex(&self.block_open, &self.block_close)?,
+            escape_char: self.escape_char,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{replace_all_from, render_template, FailureMode, RenderOptions};
+
+    #[test]
+    fn nothing_is_escaped_by_default() {
+        let mut context = HashMap::new();
+        context.insert("x", "X");
+        let replaced = replace_all_from("a \\${{ x }} C:\\${{ x }}", &context, FailureMode::FM_panic, None);
+        assert_eq!(replaced, "a \\X C:\\X");
+        let rendered = render_template("\\${% if x %}${{ x }}${% endif %}", &context, FailureMode::FM_panic, &RenderOptions::default());
+        assert_eq!(rendered.unwrap(), "\\X");
+    }
+
+    #[test]
+    fn placeholders_and_blocks_can_be_escaped() {
+        let mut context = HashMap::new();
+        context.insert("x", "X");
+        let syntax = Syntax::builder().escape_char(Some('\\')).build().unwrap();
+        let options = RenderOptions { syntax: &syntax, ..Default::default() };
+        let text = "\\${{ x }} ${{ x }} \\${% if x %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &options);
+        assert_eq!(rendered.unwrap(), "${{ x }} X ${% if x %}");
+
+        // two escape chars are one escaped escape char
+        let text = "\\\\${{ x }} \\\\\\${{ x }} \\\\${% if x %}y${% endif %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &options);
+        assert_eq!(rendered.unwrap(), "\\X \\${{ x }} \\y");
+    }
+
+    #[test]
+    fn delimiters_and_whitespace_can_be_configured() {
+        let mut context = HashMap::new();
+        context.insert("x", "X");
+        let syntax = Syntax::builder()
+            .delimiters("<<", ">>")
+            .block_delimiters("<%", "%>")
+            .escape_char(Some('!'))
+            .any_whitespace(true)
+            .build().unwrap();
+        let options = RenderOptions { syntax: &syntax, ..Default::default() };
+        let text = "$<<x>> $<<  x | d  >> !$<< x >> ${{ x }} $<%if x%>yes$<% endif %>";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &options);
+        assert_eq!(rendered.unwrap(), "X X $<< x >> ${{ x }} yes");
+
+        let err = Syntax::builder().delimiters("{%", "%}").build().unwrap_err();
+        assert_eq!(err, "Placeholders and blocks cannot both start with '{%'");
+        assert!(Syntax::builder().delimiters("", "}}").build().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use super::Context;
+
+/// a structured value that can be used as a context. keys are dotted paths
+/// into the value: `author.name` is the name field of the author map, and
+/// `posts.0.title` is the title of the first item of the posts list
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// a missing value, eg: a json null, or a field that is None.
+    /// its treated like the key doesnt exist
+    Null,
+    String(String),
+    Number(f64),
+    Bool(bool),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Map(BTreeMap::new())
+    }
+}
+
+impl Value {
+    /// any struct that can be serialized, eg: with `#[derive(Serialize)]`
+    pub fn from_serialize<T: Serialize>(value: &T) -> Result<Value, String> {
+        serde_json::to_value(value).map(Value::from).map_err(|e| e.to_string())
+    }
+
+    /// the value at a dotted path. list items are indexed by number
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        let mut value = self;
+        for part in path.split('.') {
+            value = match value {
+                Value::Map(map) => map.get(part)?,
+                Value::List(list) => list.get(part.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        match value {
+            Value::Null => None,
+            v => Some(v),
+        }
+    }
+
+    /// what a placeholder is replaced with. lists and maps
+    /// cant be inserted into a text, so they dont have a string
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(number_to_string(*n)),
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Null | Value::List(_) | Value::Map(_) => None,
+        }
+    }
+
+    /// sets a key of a map. if the value is not a map,
+    /// it is replaced by an empty map first
+    pub fn insert(&mut self, key: &str, value: impl Into<Value>) {
+        if !matches!(self, Value::Map(_)) {
+            *self = Value::default();
+        }
+        if let Value::Map(map) = self {
+            map.insert(key.to_string(), value.into());
+        }
+    }
+}
+
+/// whole numbers are written without a decimal point, eg: 3 instead of 3.0
+fn number_to_string(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Self {
+        o.map_or(Value::Null, |v| v.into())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(list: Vec<T>) -> Self {
+        Value::List(list.into_iter().map(|v| v.into()).collect())
+    }
+}
+
+impl<T: Into<Value>> From<BTreeMap<String, T>> for Value {
+    fn from(map: BTreeMap<String, T>) -> Self {
+        Value::Map(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(map: HashMap<String, T>) -> Self {
+        Value::Map(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => n.as_f64().map_or(Value::Null, Value::Number),
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(list) => list.into(),
+            serde_json::Value::Object(map) => Value::Map(map.into_iter().map(|(k, v)| (k, v.into())).collect()),
+        }
+    }
+}
+
+impl Context for Value {
+    fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+        self.get_path(key).and_then(|v| v.as_string())
+    }
+
+    fn get_list_len_from_key(&self, key: &str, _syntax_char: char) -> Option<usize> {
+        match self.get_path(key) {
+            Some(Value::List(list)) => Some(list.len()),
+            _ => None,
+        }
+    }
+}
+
+/// same as the Value context, so that json can be used without converting it
+impl Context for serde_json::Value {
+    fn get_value_from_key(&self, key: &str, _syntax_char: char) -> Option<String> {
+        match self.pointer(&format!("/{}", key.replace('.', "/")))? {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            serde_json::Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    fn get_list_len_from_key(&self, key: &str, _syntax_char: char) -> Option<usize> {
+        self.pointer(&format!("/{}", key.replace('.', "/")))?.as_array().map(|list| list.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{replace_all_from, render_template, FailureMode, RenderOptions};
+
+    #[derive(Serialize)]
+    struct Author {
+        name: String,
+        url: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct Post {
+        title: String,
+        tags: Vec<String>,
+        word_count: usize,
+        draft: bool,
+        author: Author,
+    }
+
+    fn get_post() -> Post {
+        Post {
+            title: "hello".into(),
+            tags: vec!["rust".into(), "blog".into()],
+            word_count: 300,
+            draft: false,
+            author: Author { name: "nikita".into(), url: None },
+        }
+    }
+
+    #[test]
+    fn dotted_keys_work_for_serializable_structs() {
+        let context = Value::from_serialize(&get_post()).unwrap();
+        let text = "${{ title }} by ${{ author.name }} ${{ author.url | no url }} ${{ tags.1 }} ${{ word_count }} ${{ draft }} ${{ tags | list }}";
+        let replaced = replace_all_from(text, &context, FailureMode::FM_panic, None);
+        assert_eq!(replaced, "hello by nikita no url blog 300 false list");
+
+        let text = "${% for tag in tags %}${{ tag }},${% endfor %}${% if draft %}draft${% endif %}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default());
+        assert_eq!(rendered.unwrap(), "rust,blog,");
+    }
+
+    #[test]
+    fn json_values_work_as_contexts() {
+        let json = serde_json::to_value(get_post()).unwrap();
+        let text = "${% for tag in tags %}${{ loop.index }}:${{ tag }} ${% endfor %}${{ author.name }} ${{ word_count }}";
+        let rendered = render_template(text, &json, FailureMode::FM_panic, &RenderOptions::default());
+        assert_eq!(rendered.unwrap(), "1:rust 2:blog nikita 300");
+        let value = Value::from(json);
+        assert_eq!(value.get_path("author.url"), None);
+        assert_eq!(value.get_path("tags.0"), Some(&Value::from("rust")));
+    }
+
+    #[test]
+    fn values_can_be_built_and_inserted() {
+        let mut context = Value::default();
+        context.insert("posts", vec![Value::from_serialize(&get_post()).unwrap()]);
+        context.insert("rating", 4.5);
+        context.insert("count", 3usize);
+        let text = "${% for post in posts %}${{ post.title }}: ${{ post.author.name }}${% endfor %} ${{ rating }} ${{ count }}";
+        let rendered = render_template(text, &context, FailureMode::FM_panic, &RenderOptions::default());
+        assert_eq!(rendered.unwrap(), "hello: nikita 4.5 3");
+    }
+}
@@ -0,0 +1,483 @@
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::{wait_with_timeout, CommandOutput, KillOnDrop};
+
+/// how many lines of stdout can be read ahead of the caller of ExecLines
+const LINES_READ_AHEAD: usize = 1024;
+
+/// what to do with the stdout or stderr of a child
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// keep it, so that it ends up in the ExecOutput
+    Piped,
+    /// the child writes to our stdout or stderr
+    Inherit,
+    /// throw it away
+    Null,
+}
+
+impl Capture {
+    fn to_stdio(self) -> Stdio {
+        match self {
+            Capture::Piped => Stdio::piped(),
+            Capture::Inherit => Stdio::inherit(),
+            Capture::Null => Stdio::null(),
+        }
+    }
+}
+
+/// changes to the environment that the child inherits, in the order they were made
+#[derive(Debug, Clone)]
+enum EnvChange {
+    Set(String, String),
+    Remove(String),
+    Clear,
+}
+
+/// builds a command to run, eg:
+/// ```no_run
+/// # use exechelper::Exec;
+/// # use std::time::Duration;
+/// # fn main() -> std::io::Result<()> {
+/// # let repo_path = ".";
+/// let output = Exec::new("git").args(&["show", "HEAD:README.md"])
+///     .current_dir(repo_path)
+///     .timeout(Duration::from_secs(60))
+///     .output()?;
+/// # Ok(())
+/// # }
+/// ```
+/// by default, stdin is null, stdout and stderr are captured,
+/// and there is no timeout
+#[derive(Debug, Clone)]
+pub struct Exec {
+    program: String,
+    args: Vec<String>,
+    current_dir: Option<PathBuf>,
+    env: Vec<EnvChange>,
+    stdin: Option<Vec<u8>>,
+    stdout: Capture,
+    stderr: Capture,
+    timeout: Option<Duration>,
+}
+
+/// the output of a child that ran to completion. stdout and
+/// stderr are empty unless they were captured
+#[derive(Debug)]
+pub struct ExecOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status: ExitStatus,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// the exit code, or 1 if the child was killed by a signal
+    pub fn code(&self) -> i32 {
+        self.status.code().unwrap_or(1)
+    }
+
+    pub fn stdout_string(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_string(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+impl From<ExecOutput> for CommandOutput {
+    fn from(output: ExecOutput) -> Self {
+        CommandOutput {
+            stdout: output.stdout_string(),
+            stderr: output.stderr_string(),
+            status: output.code(),
+        }
+    }
+}
+
+impl Exec {
+    pub fn new(program: &str) -> Exec {
+        Exec {
+            program: program.into(),
+            args: vec![],
+            current_dir: None,
+            env: vec![],
+            stdin: None,
+            stdout: Capture::Piped,
+            stderr: Capture::Piped,
+            timeout: None,
+        }
+    }
+
+    /// like the other functions of exechelper, the first item is the
+    /// executable, and the rest are its args. returns an error if it is empty
+    pub fn from_args(exe_and_args: &[&str]) -> Result<Exec, Error> {
+        match exe_and_args.split_first() {
+            Some((exe, args)) => Ok(Exec::new(exe).args(args)),
+            None => Err(Error::new(ErrorKind::InvalidInput, "Must provide at least the executable name")),
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args(mut self, args: &[&str]) -> Self {
+        self.args.extend(args.iter().map(|a| a.to_string()));
+        self
+    }
+
+    /// run the child in this directory instead of the current one
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn env(mut self, key: &str, val: &str) -> Self {
+        self.env.push(EnvChange::Set(key.into(), val.into()));
+        self
+    }
+
+    pub fn env_remove(mut self, key: &str) -> Self {
+        self.env.push(EnvChange::Remove(key.into()));
+        self
+    }
+
+    /// the child doesnt inherit any environment variables,
+    /// only the ones that are set after this
+    pub fn env_clear(mut self) -> Self {
+        self.env.push(EnvChange::Clear);
+        self
+    }
+
+    /// written to the stdin of the child, which is then closed
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, input: B) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    pub fn stdout(mut self, capture: Capture) -> Self {
+        self.stdout = capture;
+        self
+    }
+
+    pub fn stderr(mut self, capture: Capture) -> Self {
+        self.stderr = capture;
+        self
+    }
+
+    /// if the child doesnt exit within the timeout, output() kills
+    /// it, and returns an error of kind ErrorKind::TimedOut
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// the command without any stdio config, so that the
+    /// old spawn functions can still pass in their own
+    pub(crate) fn command(&self) -> Command {
+        let mut proc = Command::new(&self.program);
+        proc.args(&self.args);
+        if let Some(dir) = &self.current_dir {
+            proc.current_dir(dir);
+        }
+        for change in &self.env {
+            match change {
+                EnvChange::Set(k, v) => { proc.env(k, v); }
+                EnvChange::Remove(k) => { proc.env_remove(k); }
+                EnvChange::Clear => { proc.env_clear(); }
+            }
+        }
+        proc
+    }
+
+    /// starts the child without waiting for it. piped stdout and stderr
+    /// have to be read by the caller. the timeout is not used here,
+    /// see wait_with_timeout and KillOnDrop instead
+    pub fn spawn(&self) -> Result<Child, Error> {
+        let mut proc = self.command();
+        proc.stdin(if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() });
+        proc.stdout(self.stdout.to_stdio());
+        proc.stderr(self.stderr.to_stdio());
+        let mut child = proc.spawn()?;
+
+        // written from another thread, because the child might not read
+        // all of its stdin before we read its output
+        if let (Some(input), Some(mut stdin)) = (self.stdin.clone(), child.stdin.take()) {
+            thread::spawn(move || {
+                // if the child exits without reading all of it, thats up to the child
+                let _ = stdin.write_all(&input);
+            });
+        }
+        Ok(child)
+    }
+
+    /// runs the child until it exits, and returns its output
+    pub fn output(&self) -> Result<ExecOutput, Error> {
+        let mut child = KillOnDrop::new(self.spawn()?);
+
+        // the output has to be read while we wait, otherwise a child
+        // that writes a lot would block forever on a full pipe
+        let stdout_reader = child.stdout.take().map(read_in_thread);
+        let stderr_reader = child.stderr.take().map(read_in_thread);
+        let status = match self.timeout {
+            Some(timeout) => wait_with_timeout(&mut child, timeout)
+                .map_err(|e| timed_out_error(e, &self.command_line(), timeout))?,
+            None => child.wait()?,
+        };
+
+        Ok(ExecOutput {
+            stdout: stdout_reader.map_or(Ok(vec![]), join_reader)?,
+            stderr: stderr_reader.map_or(Ok(vec![]), join_reader)?,
+            status,
+        })
+    }
+
+    /// like output, but stdout is returned line by line while the child is still
+    /// running, so that a lot of output never has to be in memory all at once, eg:
+    /// ```no_run
+    /// # use exechelper::Exec;
+    /// # fn main() -> std::io::Result<()> {
+    /// let mut lines = Exec::new("git").args(&["ls-tree", "-r", "HEAD", "--name-only"]).lines()?;
+    /// for line in &mut lines {
+    ///     println!("{}", line?);
+    /// }
+    /// let output = lines.finish()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    /// stdout is always piped. the timeout is for the whole child, not for every line
+    pub fn lines(&self) -> Result<ExecLines, Error> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut child = KillOnDrop::new(self.clone().stdout(Capture::Piped).spawn()?);
+        let stdout = child.stdout.take()
+            .ok_or_else(|| Error::other("Failed to get child standard output"))?;
+
+        // read in another thread, so that we can stop
+        // waiting for the next line when the timeout is up
+        let (sender, lines) = mpsc::sync_channel(LINES_READ_AHEAD);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let failed = line.is_err();
+                // if the receiver is gone, nobody wants the rest of the lines
+                if sender.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let stderr_reader = child.stderr.take().map(read_in_thread);
+        Ok(ExecLines {
+            child,
+            lines,
+            stderr_reader,
+            deadline,
+            timeout: self.timeout,
+            command_line: self.command_line(),
+            timed_out: false,
+        })
+    }
+
+    /// the program and its args, for error messages
+    pub fn command_line(&self) -> String {
+        let mut out = self.program.clone();
+        for arg in &self.args {
+            out.push(' ');
+            out.push_str(arg);
+        }
+        out
+    }
+}
+
+/// the stdout lines of a child that is still running, see Exec::lines.
+/// yields an error if a line cant be read, eg: because it isnt utf8, or if the
+/// timeout is up. the child is killed if this is dropped before it exits
+pub struct ExecLines {
+    child: KillOnDrop,
+    lines: Receiver<Result<String, Error>>,
+    stderr_reader: Option<thread::JoinHandle<Result<Vec<u8>, Error>>>,
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+    command_line: String,
+    timed_out: bool,
+}
+
+impl ExecLines {
+    /// waits for the child to exit, and returns its exit status and stderr.
+    /// stdout is always empty: the lines that were not read yet are read and
+    /// thrown away, and if one of them cant be read, or the timeout is up,
+    /// that error is returned instead
+    pub fn finish(mut self) -> Result<ExecOutput, Error> {
+        for line in &mut self {
+            line?;
+        }
+        if let (true, Some(timeout)) = (self.timed_out, self.timeout) {
+            let err = Error::new(ErrorKind::TimedOut, "");
+            return Err(timed_out_error(err, &self.command_line, timeout));
+        }
+        let status = match (self.deadline, self.timeout) {
+            (Some(deadline), Some(timeout)) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                wait_with_timeout(&mut self.child, left)
+                    .map_err(|e| timed_out_error(e, &self.command_line, timeout))?
+            }
+            _ => self.child.wait()?,
+        };
+        Ok(ExecOutput {
+            stdout: vec![],
+            stderr: self.stderr_reader.take().map_or(Ok(vec![]), join_reader)?,
+            status,
+        })
+    }
+}
+
+impl Iterator for ExecLines {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.timed_out {
+            return None;
+        }
+        let (deadline, timeout) = match (self.deadline, self.timeout) {
+            (Some(deadline), Some(timeout)) => (deadline, timeout),
+            // the reader thread is done once stdout is closed
+            _ => return self.lines.recv().ok(),
+        };
+        match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => Some(line),
+            Err(RecvTimeoutError::Disconnected) => None,
+            Err(RecvTimeoutError::Timeout) => {
+                self.timed_out = true;
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                let err = Error::new(ErrorKind::TimedOut, "");
+                Some(Err(timed_out_error(err, &self.command_line, timeout)))
+            }
+        }
+    }
+}
+
+/// timeouts say which command timed out, every other error is kept as is
+fn timed_out_error(err: Error, command_line: &str, timeout: Duration) -> Error {
+    match err.kind() {
+        ErrorKind::TimedOut => Error::new(ErrorKind::TimedOut,
+            format!("'{}' timed out after {:?}", command_line, timeout)),
+        _ => err,
+    }
+}
+
+fn read_in_thread<R: Read + Send + 'static>(mut stream: R) -> thread::JoinHandle<Result<Vec<u8>, Error>> {
+    thread::spawn(move || {
+        let mut out = vec![];
+        stream.read_to_end(&mut out)?;
+        Ok(out)
+    })
+}
+
+fn join_reader(reader: thread::JoinHandle<Result<Vec<u8>, Error>>) -> Result<Vec<u8>, Error> {
+    reader.join().unwrap_or_else(|_| Err(Error::other("Failed to read output of child")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute_with_env;
+
+    #[test]
+    fn stdin_is_written_to_the_child() {
+        let output = Exec::new("cat").stdin(&b"hi"[..]).output().unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"hi");
+    }
+
+    #[test]
+    fn env_and_current_dir_can_be_changed() {
+        let output = Exec::new("env").env("B", "2").env_clear().env("A", "1").output().unwrap();
+        assert_eq!(output.stdout_string(), "A=1\n");
+        let output = Exec::from_args(&["sh", "-c", "echo \"$A|$B\""]).unwrap()
+            .env("A", "1").env("B", "2").env_remove("B")
+            .output().unwrap();
+        assert_eq!(output.stdout_string(), "1|\n");
+        let output = Exec::new("pwd").current_dir("/").output().unwrap();
+        assert_eq!(output.stdout_string(), "/\n");
+    }
+
+    #[test]
+    fn output_can_be_thrown_away() {
+        let output = Exec::from_args(&["sh", "-c", "echo out; echo err >&2; exit 3"]).unwrap()
+            .stdout(Capture::Null)
+            .output().unwrap();
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.stderr_string(), "err\n");
+        assert_eq!(output.code(), 3);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn invalid_input_is_an_error() {
+        assert_eq!(Exec::from_args(&[]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let err = execute_with_env(&["env"], &["A", "B"], &["1"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = crate::spawn_with_env_ex2(&["env"], &["A"], &[], Some("/"), None, None, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn lines_arrive_in_order_and_finish_has_the_status() {
+        let mut lines = Exec::from_args(&["sh", "-c", "echo a; echo b; echo err >&2; exit 3"]).unwrap()
+            .lines().unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "a");
+        assert_eq!(lines.next().unwrap().unwrap(), "b");
+        assert!(lines.next().is_none());
+        let output = lines.finish().unwrap();
+        assert_eq!(output.code(), 3);
+        assert_eq!(output.stderr_string(), "err\n");
+        assert!(output.stdout.is_empty());
+
+        // the lines that werent read dont end up anywhere
+        let lines = Exec::from_args(&["sh", "-c", "seq 1 100000"]).unwrap().lines().unwrap();
+        assert!(lines.finish().unwrap().success());
+    }
+
+    #[test]
+    fn dropping_lines_kills_the_child() {
+        let mut lines = Exec::new("yes").lines().unwrap();
+        let pid = lines.child.id();
+        assert_eq!(lines.next().unwrap().unwrap(), "y");
+        drop(lines);
+        assert!(!crate::executed_successfully(&["kill", "-0", &pid.to_string()]));
+    }
+
+    #[test]
+    fn lines_time_out_while_waiting_for_a_line() {
+        let started = Instant::now();
+        let mut lines = Exec::from_args(&["sh", "-c", "echo a; sleep 5; echo b"]).unwrap()
+            .timeout(Duration::from_millis(200))
+            .lines().unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "a");
+        let err = lines.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(lines.next().is_none());
+        assert_eq!(lines.finish().unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn old_functions_still_work() {
+        let output = execute_with_env(&["sh", "-c", "echo $A"], &["A"], &["1"]).unwrap();
+        assert_eq!(output.stdout, "1\n");
+        let child = crate::spawn_with_env_ex2(&["sh", "-c", "echo $A; pwd"], &["A"], &["2"], Some("/"),
+            Some(Stdio::null()), Some(Stdio::null()), Some(Stdio::piped())).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n/\n");
+    }
+}
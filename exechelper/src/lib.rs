@@ -1,5 +1,15 @@
-use std::process::Command;
-use std::{io::Error, process::Stdio, ffi::OsStr, path::Path};
+use std::process::{Child, ExitStatus};
+use std::{io::Error, process::Stdio, path::Path};
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod exec;
+pub use exec::*;
+
+/// how often we check if a child with a timeout has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct CommandOutput {
     pub stdout: String,
@@ -34,47 +44,108 @@ pub fn execute_with_env(
     keys: &[&str],
     vals: &[&str],
 ) -> Result<CommandOutput, Error> {
-    // at the very least must provide the executable name
-    assert!(exe_and_args.len() >= 1);
-    assert!(keys.len() == vals.len());
-
-    let mut proc = Command::new(exe_and_args[0]);
-    for arg in exe_and_args.iter().skip(1) {
-        proc.arg(arg);
-    }
-
-    let it = keys.iter().zip(vals.iter());
-    for (k, v) in it {
-        proc.env(k, v);
-    }
-
-    proc.stdin(Stdio::null());
-    let output = proc.output();
-
-    match output {
-        Err(e) => Err(e),
-        Ok(out) => {
-            let errput_str_cow = String::from_utf8_lossy(&out.stderr);
-            let output_str_cow = String::from_utf8_lossy(&out.stdout);
-            Ok(
-                CommandOutput {
-                    stdout: output_str_cow.into_owned(),
-                    stderr: errput_str_cow.into_owned(),
-                    status: out.status.code().unwrap_or(1),
-                }
-            )
-        }
-    }
-} 
+    let output = exec_with_env(exe_and_args, keys, vals)?.output()?;
+    Ok(output.into())
+}
 
 pub fn execute(exe_and_args: &[&str]) -> Result<CommandOutput, Error> {
     execute_with_env(exe_and_args, &[], &[])
 }
 
-/// optionally pass in what kind of stdio config
-/// you want to use for each stream. passing None
-/// will use whatever the default is.
-pub fn spawn_with_env_ex_actual<P: AsRef<Path>>(
+/// like execute, but if the command doesnt exit within the timeout
+/// it is killed, and an error of kind ErrorKind::TimedOut is returned
+pub fn execute_with_timeout(exe_and_args: &[&str], timeout: Duration) -> Result<CommandOutput, Error> {
+    execute_with_env_timeout(exe_and_args, &[], &[], timeout)
+}
+
+/// like execute_with_env, but if the command doesnt exit within the timeout
+/// it is killed, and an error of kind ErrorKind::TimedOut is returned
+pub fn execute_with_env_timeout(
+    exe_and_args: &[&str],
+    keys: &[&str],
+    vals: &[&str],
+    timeout: Duration,
+) -> Result<CommandOutput, Error> {
+    let output = exec_with_env(exe_and_args, keys, vals)?.timeout(timeout).output()?;
+    Ok(output.into())
+}
+
+/// the keys and vals are parallel slices of environment variables to set
+fn exec_with_env(exe_and_args: &[&str], keys: &[&str], vals: &[&str]) -> Result<Exec, Error> {
+    if keys.len() != vals.len() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Must provide a value for every environment variable"));
+    }
+    let exec = Exec::from_args(exe_and_args)?;
+    Ok(keys.iter().zip(vals.iter()).fold(exec, |exec, (k, v)| exec.env(k, v)))
+}
+
+/// waits for the child to exit. if it doesnt exit within the timeout, it is
+/// killed, and an error of kind ErrorKind::TimedOut is returned
+pub fn wait_with_timeout(child: &mut Child, timeout: Duration) -> Result<ExitStatus, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            // it might have exited right before we kill it,
+            // but then it didnt exit within the timeout either
+            let _ = child.kill();
+            child.wait()?;
+            return Err(Error::new(ErrorKind::TimedOut, format!("Child timed out after {:?}", timeout)));
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// kills the child when it is dropped, if it is still running. this way
+/// a child cant outlive us when we return early, eg: because of an error.
+/// derefs to the child, so it can be used like one
+pub struct KillOnDrop {
+    child: Option<Child>,
+}
+
+impl KillOnDrop {
+    pub fn new(child: Child) -> KillOnDrop {
+        KillOnDrop { child: Some(child) }
+    }
+
+    /// the child will no longer be killed when dropped
+    pub fn into_inner(mut self) -> Child {
+        // the child is only taken here, so it is always Some before this
+        self.child.take().unwrap()
+    }
+}
+
+impl Deref for KillOnDrop {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        self.child.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for KillOnDrop {
+    fn deref_mut(&mut self) -> &mut Child {
+        self.child.as_mut().unwrap()
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+/// what the spawn functions are built on. passing None
+/// for a stream will use whatever the default is
+fn spawn_with_env_ex_actual<P: AsRef<Path>>(
     exe_and_args: &[&str],
     keys: &[&str],
     vals: &[&str],
@@ -83,33 +154,19 @@ pub fn spawn_with_env_ex_actual<P: AsRef<Path>>(
     read_stderr: Option<Stdio>,
     read_stdout: Option<Stdio>,
 ) -> Result<std::process::Child, Error> {
-    // at the very least must provide the executable name
-    assert!(exe_and_args.len() >= 1);
-    assert!(keys.len() == vals.len());
-
-    let mut proc = Command::new(exe_and_args[0]);
+    let mut exec = exec_with_env(exe_and_args, keys, vals)?;
     if let Some(location) = location {
-        proc.current_dir(location);
-    }
-    for arg in exe_and_args.iter().skip(1) {
-        proc.arg(arg);
+        exec = exec.current_dir(location);
     }
 
-    let it = keys.iter().zip(vals.iter());
-    for (k, v) in it {
-        proc.env(k, v);
-    }
-
-    let mut read_stdin = read_stdin;
-    let mut read_stderr = read_stderr;
-    let mut read_stdout = read_stdout;
-    if let Some(cfg) = read_stdin.take() {
+    let mut proc = exec.command();
+    if let Some(cfg) = read_stdin {
         proc.stdin(cfg);
     }
-    if let Some(cfg) = read_stderr.take() {
+    if let Some(cfg) = read_stderr {
         proc.stderr(cfg);
     }
-    if let Some(cfg) = read_stdout.take() {
+    if let Some(cfg) = read_stdout {
         proc.stdout(cfg);
     }
 
@@ -119,6 +176,7 @@ pub fn spawn_with_env_ex_actual<P: AsRef<Path>>(
 /// optionally pass in what kind of stdio config
 /// you want to use for each stream. passing None
 /// will use whatever the default is.
+#[deprecated(note = "use Exec, eg: Exec::from_args(exe_and_args)?.env(key, val).stdout(Capture::Piped).spawn()")]
 pub fn spawn_with_env_ex(
     exe_and_args: &[&str],
     keys: &[&str],
@@ -131,6 +189,8 @@ pub fn spawn_with_env_ex(
     spawn_with_env_ex_actual(exe_and_args, keys, vals, no_path, read_stdin, read_stderr, read_stdout)
 }
 
+/// like spawn_with_env_ex, but runs in the location directory
+#[deprecated(note = "use Exec, eg: Exec::from_args(exe_and_args)?.current_dir(location).spawn()")]
 pub fn spawn_with_env_ex2<P: AsRef<Path>>(
     exe_and_args: &[&str],
     keys: &[&str],
@@ -153,6 +213,60 @@ pub fn spawn_with_env(
     keys: &[&str],
     vals: &[&str],
 ) -> Result<std::process::Child, Error> {
-    spawn_with_env_ex(exe_and_args, keys, vals,
+    let no_path: Option<&Path> = None;
+    spawn_with_env_ex_actual(exe_and_args, keys, vals, no_path,
         Some(Stdio::null()), Some(Stdio::null()), None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_running(pid: u32) -> bool {
+        executed_successfully(&["kill", "-0", &pid.to_string()])
+    }
+
+    #[test]
+    fn wait_with_timeout_kills_and_reaps_the_child() {
+        let mut child = spawn(&["sh", "-c", "sleep 5"]).unwrap();
+        let started = Instant::now();
+        let err = wait_with_timeout(&mut child, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // the exit status was already collected, so its not a zombie
+        assert!(child.try_wait().unwrap().is_some());
+        assert!(!is_running(child.id()));
+
+        let started = Instant::now();
+        let err = execute_with_timeout(&["sh", "-c", "sleep 5"], Duration::from_millis(100)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "'sh -c sleep 5' timed out after 100ms");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn lots_of_output_doesnt_block_a_timeout() {
+        // more than fits into a pipe, so the child blocks until we read it
+        let output = execute_with_env_timeout(
+            &["sh", "-c", "yes | head -c 300000; yes | head -c 200000 >&2"],
+            &[], &[], Duration::from_secs(10)).unwrap();
+        assert_eq!(output.status, 0);
+        assert_eq!(output.stdout.len(), 300000);
+        assert_eq!(output.stderr.len(), 200000);
+    }
+
+    #[test]
+    fn kill_on_drop_kills_the_child() {
+        let child = KillOnDrop::new(spawn(&["sleep", "30"]).unwrap());
+        let pid = child.id();
+        assert!(is_running(pid));
+        drop(child);
+        assert!(!is_running(pid));
+
+        // unless it was taken out of the guard
+        let mut child = KillOnDrop::new(spawn(&["sleep", "30"]).unwrap()).into_inner();
+        assert!(is_running(child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}